@@ -0,0 +1,443 @@
+use std::{
+    f64::consts::TAU,
+    fmt::{Debug, Display},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::megachip::Sample;
+
+/// The sample rate used when one isn't given
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+/// The highest sample rate a recording can be made at
+pub const MAX_SAMPLE_RATE: u32 = 192_000;
+/// The pitch of the beep, for everything but XO-CHIP programs that have loaded an audio pattern
+pub const BEEP_FREQUENCY: f64 = 440.0;
+
+/// Something that plays the sound a program makes.
+///
+/// Each change comes with the emulated time it happened at, so a sink can line the sound up
+/// with the rest of the program however fast it's really running.
+pub trait AudioSink: Send {
+    /// Called when the sound timer turns the beeper on or off
+    fn beeper(&mut self, on: bool, at: Duration);
+    /// Called when an XO-CHIP program changes the audio pattern buffer or the pitch it's played at
+    fn pattern(&mut self, pattern: &[u8; 16], pitch: u8, at: Duration);
+    /// Called when a MegaChip program starts a digitised sound, or stops it with `None`
+    fn sample(&mut self, sample: Option<&Sample>, at: Duration);
+}
+
+/// A sink that throws the sound away, for running without audio
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NullSink;
+
+impl AudioSink for NullSink {
+    fn beeper(&mut self, _on: bool, _at: Duration) {}
+
+    fn pattern(&mut self, _pattern: &[u8; 16], _pitch: u8, _at: Duration) {}
+
+    fn sample(&mut self, _sample: Option<&Sample>, _at: Duration) {}
+}
+
+// the sink the CPU sends its sound to, which is a `NullSink` until something else is connected
+pub(crate) struct Audio(pub Box<dyn AudioSink>);
+
+impl Default for Audio {
+    fn default() -> Self {
+        Self(Box::new(NullSink))
+    }
+}
+
+impl Debug for Audio {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Audio")
+    }
+}
+
+/// The shape of the beep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Waveform {
+    #[default]
+    Square,
+    Sine,
+    Triangle,
+    Sawtooth,
+}
+
+impl Waveform {
+    pub const ALL: [Waveform; 4] = [
+        Waveform::Square,
+        Waveform::Sine,
+        Waveform::Triangle,
+        Waveform::Sawtooth,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Waveform::Square => "square",
+            Waveform::Sine => "sine",
+            Waveform::Triangle => "triangle",
+            Waveform::Sawtooth => "sawtooth",
+        }
+    }
+
+    // the level of the wave at `phase` through a cycle, from -1 to 1
+    fn level(self, phase: f64) -> f64 {
+        match self {
+            Waveform::Square if phase < 0.5 => 1.0,
+            Waveform::Square => -1.0,
+            Waveform::Sine => (phase * TAU).sin(),
+            Waveform::Triangle => 4.0 * (phase - 0.5).abs() - 1.0,
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+        }
+    }
+}
+
+impl Display for Waveform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Waveform {
+    type Err = UnknownWaveform;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Waveform::ALL
+            .into_iter()
+            .find(|w| w.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| UnknownWaveform(s.to_string()))
+    }
+}
+
+/// Returned when a waveform name isn't recognised
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownWaveform(pub String);
+
+impl Display for UnknownWaveform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown waveform '{}', expected one of:", self.0)?;
+        for waveform in Waveform::ALL {
+            write!(f, " {}", waveform)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnknownWaveform {}
+
+/// How many bits of the XO-CHIP pattern buffer are played a second at `pitch`
+pub fn pattern_rate(pitch: u8) -> f64 {
+    4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0)
+}
+
+/// Turns the beeper, and the XO-CHIP pattern buffer once a program has loaded one, into samples.
+///
+/// MegaChip's digitised sound plays over the top, whether or not the beeper is on.
+#[derive(Debug, Clone)]
+pub struct Synth {
+    rate: u32,
+    waveform: Waveform,
+    frequency: f64,
+    volume: f32,
+    on: bool,
+    // the XO-CHIP pattern buffer and the rate its bits are played at
+    pattern: Option<([u8; 16], f64)>,
+    // how far through a cycle of the wave, or of the whole pattern, playback has got, from 0 to 1
+    phase: f64,
+    // the digitised sound and how far through it playback has got, in its own samples
+    digitised: Option<(Sample, f64)>,
+}
+
+impl Synth {
+    pub fn new(rate: u32, waveform: Waveform) -> Self {
+        Self {
+            rate: rate.max(1),
+            waveform,
+            frequency: BEEP_FREQUENCY,
+            volume: 0.25,
+            on: false,
+            pattern: None,
+            phase: 0.0,
+            digitised: None,
+        }
+    }
+
+    /// Samples per second
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn waveform(&self) -> Waveform {
+        self.waveform
+    }
+
+    pub fn set_waveform(&mut self, waveform: Waveform) {
+        self.waveform = waveform;
+    }
+
+    /// Change the pitch of the beep, in Hz
+    pub fn set_frequency(&mut self, frequency: f64) {
+        self.frequency = frequency;
+    }
+
+    /// Change how loud the sound is, from 0 to 1
+    pub fn set_volume(&mut self, volume: f32) {
+        self.volume = volume.clamp(0.0, 1.0);
+    }
+
+    pub fn is_on(&self) -> bool {
+        self.on
+    }
+
+    pub fn set_beeper(&mut self, on: bool) {
+        self.on = on;
+    }
+
+    /// Play the XO-CHIP pattern buffer at `pitch` instead of the waveform
+    pub fn set_pattern(&mut self, pattern: &[u8; 16], pitch: u8) {
+        self.pattern = Some((*pattern, pattern_rate(pitch)));
+    }
+
+    /// Play a MegaChip digitised sound from the start, or stop it with `None`
+    pub fn set_sample(&mut self, sample: Option<&Sample>) {
+        self.digitised = sample.map(|s| (s.clone(), 0.0));
+    }
+
+    /// Whether a digitised sound is still playing
+    pub fn is_playing_sample(&self) -> bool {
+        self.digitised.is_some()
+    }
+
+    /// The next sample, from -volume to volume
+    pub fn next_sample(&mut self) -> f32 {
+        let level = self.next_beep() + self.next_digitised();
+        level.clamp(-1.0, 1.0) as f32 * self.volume
+    }
+
+    // the beeper's level, from -1 to 1
+    fn next_beep(&mut self) -> f64 {
+        if !self.on {
+            return 0.0;
+        }
+
+        let (level, cycles_per_second) = match &self.pattern {
+            Some((pattern, rate)) => {
+                let bit = (self.phase * 128.0) as usize % 128;
+                let set = pattern[bit / 8] & (0x80 >> (bit % 8)) != 0;
+                (if set { 1.0 } else { -1.0 }, rate / 128.0)
+            }
+            None => (self.waveform.level(self.phase), self.frequency),
+        };
+        self.phase = (self.phase + cycles_per_second / self.rate as f64).fract();
+
+        level
+    }
+
+    // the digitised sound's level, from -1 to 1
+    fn next_digitised(&mut self) -> f64 {
+        let Some((sample, position)) = &mut self.digitised else {
+            return 0.0;
+        };
+        let Some(&byte) = sample.data.get(*position as usize) else {
+            self.digitised = None;
+            return 0.0;
+        };
+
+        // unsigned samples, with silence at 128
+        let level = (byte as f64 - 128.0) / 128.0;
+        *position += sample.rate as f64 / self.rate as f64;
+        if sample.looping && *position >= sample.data.len() as f64 {
+            *position %= sample.data.len() as f64;
+        }
+
+        level
+    }
+
+    /// Fill `out` with the next samples
+    pub fn fill(&mut self, out: &mut [f32]) {
+        for sample in out {
+            *sample = self.next_sample();
+        }
+    }
+}
+
+impl AudioSink for Synth {
+    fn beeper(&mut self, on: bool, _at: Duration) {
+        self.set_beeper(on);
+    }
+
+    fn pattern(&mut self, pattern: &[u8; 16], pitch: u8, _at: Duration) {
+        self.set_pattern(pattern, pitch);
+    }
+
+    fn sample(&mut self, sample: Option<&Sample>, _at: Duration) {
+        self.set_sample(sample);
+    }
+}
+
+// lets the CPU control a sink that's also used somewhere else, like a synth being played on another thread
+impl<S: AudioSink> AudioSink for Arc<Mutex<S>> {
+    fn beeper(&mut self, on: bool, at: Duration) {
+        self.lock().unwrap().beeper(on, at);
+    }
+
+    fn pattern(&mut self, pattern: &[u8; 16], pitch: u8, at: Duration) {
+        self.lock().unwrap().pattern(pattern, pitch, at);
+    }
+
+    fn sample(&mut self, sample: Option<&Sample>, at: Duration) {
+        self.lock().unwrap().sample(sample, at);
+    }
+}
+
+// sends the sound to every sink at once
+impl AudioSink for Vec<Box<dyn AudioSink>> {
+    fn beeper(&mut self, on: bool, at: Duration) {
+        for sink in self {
+            sink.beeper(on, at);
+        }
+    }
+
+    fn pattern(&mut self, pattern: &[u8; 16], pitch: u8, at: Duration) {
+        for sink in self {
+            sink.pattern(pattern, pitch, at);
+        }
+    }
+
+    fn sample(&mut self, sample: Option<&Sample>, at: Duration) {
+        for sink in self {
+            sink.sample(sample, at);
+        }
+    }
+}
+
+/// Returned when the audio device can't be opened
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioError(pub String);
+
+impl Display for AudioError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unable to play audio: {}", self.0)
+    }
+}
+
+impl std::error::Error for AudioError {}
+
+#[cfg(feature = "audio")]
+pub use output::AudioOutput;
+
+#[cfg(feature = "audio")]
+mod output {
+    use std::sync::{Arc, Mutex};
+
+    use cpal::{
+        traits::{DeviceTrait, HostTrait, StreamTrait},
+        FromSample, SampleFormat, SizedSample, Stream, StreamConfig,
+    };
+
+    use super::{AudioError, Synth, Waveform};
+
+    /// Plays a synth on the default output device, for as long as it's kept around
+    pub struct AudioOutput {
+        // the stream can't be sent between threads, so the CPU is given the synth instead
+        _stream: Stream,
+        synth: Arc<Mutex<Synth>>,
+    }
+
+    impl AudioOutput {
+        pub fn new(waveform: Waveform) -> Result<Self, AudioError> {
+            let error = |e: &dyn std::error::Error| AudioError(e.to_string());
+            let device = cpal::default_host()
+                .default_output_device()
+                .ok_or_else(|| AudioError("no output device".to_string()))?;
+            let supported = device.default_output_config().map_err(|e| error(&e))?;
+            let format = supported.sample_format();
+            let config: StreamConfig = supported.into();
+            let synth = Arc::new(Mutex::new(Synth::new(config.sample_rate.0, waveform)));
+
+            let stream = match format {
+                SampleFormat::F32 => build::<f32>(&device, &config, synth.clone()),
+                SampleFormat::I16 => build::<i16>(&device, &config, synth.clone()),
+                SampleFormat::U16 => build::<u16>(&device, &config, synth.clone()),
+                f => return Err(AudioError(format!("unsupported sample format {}", f))),
+            }
+            .map_err(|e| error(&e))?;
+            stream.play().map_err(|e| error(&e))?;
+
+            Ok(Self {
+                _stream: stream,
+                synth,
+            })
+        }
+
+        /// The synth being played, which is what the CPU should be given as its audio sink
+        pub fn synth(&self) -> Arc<Mutex<Synth>> {
+            self.synth.clone()
+        }
+    }
+
+    // play the synth on every channel
+    fn build<T: SizedSample + FromSample<f32>>(
+        device: &cpal::Device,
+        config: &StreamConfig,
+        synth: Arc<Mutex<Synth>>,
+    ) -> Result<Stream, cpal::BuildStreamError> {
+        let channels = config.channels as usize;
+        device.build_output_stream(
+            config,
+            move |data: &mut [T], _| {
+                let mut synth = synth.lock().unwrap();
+                for frame in data.chunks_mut(channels) {
+                    let sample = T::from_sample(synth.next_sample());
+                    frame.fill(sample);
+                }
+            },
+            |e| eprintln!("Audio stream error: {}", e),
+            None,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digitised_sound_plays_without_the_beeper() {
+        let mut synth = Synth::new(8000, Waveform::Square);
+        synth.set_volume(1.0);
+        let sample = Sample {
+            rate: 4000,
+            data: vec![0xFF, 0x00],
+            looping: false,
+        };
+        synth.sample(Some(&sample), Duration::ZERO);
+
+        // each of the sound's samples lasts two of the synth's
+        let mut out = [0.0; 5];
+        synth.fill(&mut out);
+        assert_eq!(out, [127.0 / 128.0, 127.0 / 128.0, -1.0, -1.0, 0.0]);
+        assert!(!synth.is_playing_sample());
+    }
+
+    #[test]
+    fn looping_digitised_sound() {
+        let mut synth = Synth::new(4000, Waveform::Square);
+        synth.set_volume(1.0);
+        let sample = Sample {
+            rate: 4000,
+            data: vec![0xC0, 0x40],
+            looping: true,
+        };
+        synth.set_sample(Some(&sample));
+
+        let mut out = [0.0; 5];
+        synth.fill(&mut out);
+        assert_eq!(out, [0.5, -0.5, 0.5, -0.5, 0.5]);
+        synth.set_sample(None);
+        assert_eq!(synth.next_sample(), 0.0);
+    }
+}
@@ -0,0 +1,654 @@
+use crate::{
+    cpu::CPU,
+    error::CpuError,
+    platform::{Platform, Profile},
+    quirks::Quirks,
+    random::RandomSource,
+};
+
+/// Sets up a CPU in a particular state, for testing individual instructions.
+///
+/// ```
+/// use potato::CpuBuilder;
+///
+/// // 8014 - V0 += V1
+/// let mut cpu = CpuBuilder::new()
+///     .register(0, 0xFF)
+///     .register(1, 2)
+///     .instructions(&[0x8014])
+///     .build()
+///     .unwrap();
+/// cpu.run_instructions(1).unwrap();
+/// assert_eq!(cpu.registers()[0], 1);
+/// assert_eq!(cpu.registers()[0xF], 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CpuBuilder {
+    profile: Profile,
+    program: Vec<u8>,
+    registers: [u8; 16],
+    index: u32,
+    pc: Option<usize>,
+    stack: Vec<usize>,
+    delay_timer: u8,
+    sound_timer: u8,
+    memory: Vec<(usize, Vec<u8>)>,
+    keypad: [[bool; 16]; 2],
+    rng: Option<RandomSource>,
+}
+
+impl CpuBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn platform(self, platform: Platform) -> Self {
+        self.profile(platform.profile())
+    }
+
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.profile = profile;
+        self
+    }
+
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.profile.quirks = quirks;
+        self
+    }
+
+    /// The program loaded at the profile's load address
+    pub fn program(mut self, program: &[u8]) -> Self {
+        self.program = program.to_vec();
+        self
+    }
+
+    /// A program made of two byte instructions
+    pub fn instructions(self, instructions: &[u16]) -> Self {
+        let program: Vec<u8> = instructions.iter().flat_map(|i| i.to_be_bytes()).collect();
+        self.program(&program)
+    }
+
+    pub fn register(mut self, x: usize, value: u8) -> Self {
+        self.registers[x & 0xF] = value;
+        self
+    }
+
+    pub fn registers(mut self, registers: [u8; 16]) -> Self {
+        self.registers = registers;
+        self
+    }
+
+    pub fn index(mut self, index: u32) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Start somewhere other than the load address
+    pub fn pc(mut self, pc: usize) -> Self {
+        self.pc = Some(pc);
+        self
+    }
+
+    /// Return addresses, from the outermost call to the innermost
+    pub fn stack(mut self, addrs: &[usize]) -> Self {
+        self.stack = addrs.to_vec();
+        self
+    }
+
+    pub fn delay_timer(mut self, value: u8) -> Self {
+        self.delay_timer = value;
+        self
+    }
+
+    pub fn sound_timer(mut self, value: u8) -> Self {
+        self.sound_timer = value;
+        self
+    }
+
+    /// Bytes copied into memory at `addr` once the program has been loaded
+    pub fn memory(mut self, addr: usize, bytes: &[u8]) -> Self {
+        self.memory.push((addr, bytes.to_vec()));
+        self
+    }
+
+    /// Hold down a key on the first keypad
+    pub fn key(mut self, key: usize) -> Self {
+        self.keypad[0][key & 0xF] = true;
+        self
+    }
+
+    pub fn keypad(mut self, keypad: [[bool; 16]; 2]) -> Self {
+        self.keypad = keypad;
+        self
+    }
+
+    /// Where CXNN gets its random numbers from, otherwise it's seeded with 0 so tests are repeatable
+    pub fn random_source(mut self, rng: RandomSource) -> Self {
+        self.rng = Some(rng);
+        self
+    }
+
+    /// Make the CPU, failing if the program, memory or stack don't fit
+    pub fn build(self) -> Result<CPU, CpuError> {
+        let mut cpu = CPU::try_with_profile(self.profile)?;
+        cpu.load_program(&self.program)?;
+        for (addr, bytes) in &self.memory {
+            cpu.write_memory(*addr, bytes)?;
+        }
+        cpu.set_call_stack(&self.stack)?;
+        for (x, value) in self.registers.into_iter().enumerate() {
+            cpu.set_register(x, value);
+        }
+        cpu.set_index(self.index);
+        if let Some(pc) = self.pc {
+            cpu.set_pc(pc);
+        }
+        cpu.set_delay_timer(self.delay_timer);
+        cpu.set_sound_timer(self.sound_timer);
+        cpu.keypad = self.keypad;
+        cpu.set_random_source(self.rng.unwrap_or(RandomSource::seeded(0)));
+
+        Ok(cpu)
+    }
+
+    /// Make the CPU and run `n` instructions on it
+    pub fn run(self, n: usize) -> Result<CPU, CpuError> {
+        let mut cpu = self.build()?;
+        cpu.run_instructions(n)?;
+        Ok(cpu)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip8x::PortHandler;
+    use std::sync::{Arc, Mutex};
+
+    fn run(platform: Platform, builder: CpuBuilder) -> CPU {
+        builder.platform(platform).run(1).unwrap()
+    }
+
+    #[test]
+    fn shifts() {
+        // 8126 - V1 = V2 >> 1 on the VIP, V1 >> 1 on the HP 48
+        let shr = CpuBuilder::new()
+            .register(1, 0x01)
+            .register(2, 0x83)
+            .instructions(&[0x8126]);
+        let cpu = run(Platform::CosmacVip, shr.clone());
+        assert_eq!((cpu.registers()[1], cpu.registers()[0xF]), (0x41, 1));
+        let cpu = run(Platform::Schip11, shr);
+        assert_eq!((cpu.registers()[1], cpu.registers()[0xF]), (0x00, 1));
+
+        // 812E - V1 = V2 << 1 on the VIP, V1 << 1 on the HP 48
+        let shl = CpuBuilder::new()
+            .register(1, 0x41)
+            .register(2, 0x81)
+            .instructions(&[0x812E]);
+        let cpu = run(Platform::CosmacVip, shl.clone());
+        assert_eq!((cpu.registers()[1], cpu.registers()[0xF]), (0x02, 1));
+        let cpu = run(Platform::Schip11, shl);
+        assert_eq!((cpu.registers()[1], cpu.registers()[0xF]), (0x82, 0));
+
+        // the flag is written last, so it wins when X is F
+        let cpu = run(
+            Platform::CosmacVip,
+            CpuBuilder::new().register(1, 0x02).instructions(&[0x8F16]),
+        );
+        assert_eq!(cpu.registers()[0xF], 0);
+    }
+
+    #[test]
+    fn logic_resets_vf() {
+        for (instr, result) in [(0x8121, 0x3C), (0x8122, 0x00), (0x8123, 0x3C)] {
+            let logic = CpuBuilder::new()
+                .register(1, 0x0C)
+                .register(2, 0x30)
+                .register(0xF, 5)
+                .instructions(&[instr]);
+            let cpu = run(Platform::CosmacVip, logic.clone());
+            assert_eq!((cpu.registers()[1], cpu.registers()[0xF]), (result, 0));
+            let cpu = run(Platform::SchipModern, logic);
+            assert_eq!((cpu.registers()[1], cpu.registers()[0xF]), (result, 5));
+        }
+    }
+
+    #[test]
+    fn jump_with_offset() {
+        // B210 - jump to 0x210 plus V0, or plus V2 on the HP 48
+        let jump = CpuBuilder::new()
+            .register(0, 4)
+            .register(2, 8)
+            .instructions(&[0xB210]);
+        assert_eq!(run(Platform::CosmacVip, jump.clone()).pc(), 0x214);
+        assert_eq!(run(Platform::Schip11, jump.clone()).pc(), 0x218);
+        assert_eq!(run(Platform::XoChip, jump).pc(), 0x214);
+    }
+
+    #[test]
+    fn load_store_index() {
+        let registers = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+        let store = CpuBuilder::new()
+            .registers(registers)
+            .index(0x300)
+            .instructions(&[0xF255]);
+        for (platform, index) in [
+            (Platform::CosmacVip, 0x303),
+            (Platform::Chip48, 0x302),
+            (Platform::Schip11, 0x300),
+        ] {
+            let cpu = run(platform, store.clone());
+            assert_eq!(cpu.read_memory(0x300, 4).unwrap(), [1, 2, 3, 0]);
+            assert_eq!(cpu.index(), index, "{}", platform);
+        }
+
+        let load = CpuBuilder::new()
+            .memory(0x300, &[9, 8, 7, 6])
+            .index(0x300)
+            .instructions(&[0xF265]);
+        for (platform, index) in [
+            (Platform::CosmacVip, 0x303),
+            (Platform::Chip48, 0x302),
+            (Platform::Schip11, 0x300),
+        ] {
+            let cpu = run(platform, load.clone());
+            assert_eq!(cpu.registers()[..4], [9, 8, 7, 0]);
+            assert_eq!(cpu.index(), index, "{}", platform);
+        }
+    }
+
+    #[test]
+    fn wait_for_key() {
+        // F30A - wait for a key and store it in V3. Key 1 is already down, so it doesn't count
+        let wait = CpuBuilder::new().key(1).instructions(&[0xF30A]);
+
+        let mut cpu = wait.clone().platform(Platform::CosmacVip).build().unwrap();
+        cpu.run_instructions(2).unwrap();
+        assert_eq!(cpu.pc(), 0x200);
+        cpu.set_key(0, 5, true);
+        cpu.run_instructions(2).unwrap();
+        // the VIP waits for the key to come back up
+        assert_eq!(cpu.pc(), 0x200);
+        cpu.set_key(0, 5, false);
+        cpu.run_instructions(1).unwrap();
+        assert_eq!((cpu.pc(), cpu.registers()[3]), (0x202, 5));
+
+        let mut cpu = wait.platform(Platform::Schip11).build().unwrap();
+        cpu.run_instructions(2).unwrap();
+        assert_eq!(cpu.pc(), 0x200);
+        cpu.set_key(0, 5, true);
+        cpu.run_instructions(1).unwrap();
+        assert_eq!((cpu.pc(), cpu.registers()[3]), (0x202, 5));
+    }
+
+    fn lit(cpu: &CPU) -> Vec<(usize, usize)> {
+        cpu.framebuffer()
+            .pixels()
+            .filter(|(_, _, px)| *px != 0)
+            .map(|(x, y, _)| (x, y))
+            .collect()
+    }
+
+    #[test]
+    fn schip10_has_no_scrolling() {
+        let scroll = CpuBuilder::new().instructions(&[0x00C1]);
+        assert!(scroll.clone().platform(Platform::Schip10).run(1).is_err());
+        assert!(scroll.platform(Platform::Schip11).run(1).is_ok());
+    }
+
+    #[test]
+    fn schip11_draws_lores_on_the_hires_display() {
+        // draw two pixels in low resolution, then switch to high resolution
+        let draw = CpuBuilder::new()
+            .memory(0x300, &[0xC0])
+            .index(0x300)
+            .instructions(&[0xD011, 0x00FF]);
+
+        let cpu = draw.clone().platform(Platform::Schip11).run(1).unwrap();
+        assert_eq!(cpu.resolution(), (128, 64));
+        let pixels = vec![
+            (0, 0),
+            (1, 0),
+            (2, 0),
+            (3, 0),
+            (0, 1),
+            (1, 1),
+            (2, 1),
+            (3, 1),
+        ];
+        assert_eq!(lit(&cpu), pixels);
+        // the screen is kept when the resolution changes
+        let cpu = draw.clone().platform(Platform::Schip11).run(2).unwrap();
+        assert_eq!(lit(&cpu), pixels);
+
+        let cpu = draw.clone().platform(Platform::SchipModern).run(1).unwrap();
+        assert_eq!(cpu.resolution(), (64, 32));
+        assert_eq!(lit(&cpu), [(0, 0), (1, 0)]);
+        let cpu = draw.platform(Platform::SchipModern).run(2).unwrap();
+        assert_eq!(cpu.resolution(), (128, 64));
+        assert_eq!(lit(&cpu), []);
+    }
+
+    #[test]
+    fn schip11_scrolls_half_a_lores_pixel() {
+        let scroll = CpuBuilder::new()
+            .memory(0x300, &[0x80])
+            .index(0x300)
+            .instructions(&[0xD011, 0x00C1]);
+        let cpu = scroll.clone().platform(Platform::Schip11).run(2).unwrap();
+        assert_eq!(lit(&cpu), [(0, 1), (1, 1), (0, 2), (1, 2)]);
+        let cpu = scroll.platform(Platform::SchipModern).run(2).unwrap();
+        assert_eq!(lit(&cpu), [(0, 1)]);
+    }
+
+    #[test]
+    fn schip11_counts_rows_for_vf() {
+        // in high resolution, draw the same 3 row sprite twice, then one that's clipped
+        let draw = CpuBuilder::new()
+            .memory(0x300, &[0xFF, 0xFF, 0xFF])
+            .index(0x300)
+            .register(2, 62)
+            .instructions(&[0x00FF, 0xD013, 0xD013, 0xD023]);
+        for (platform, collided, clipped) in
+            [(Platform::Schip11, 3, 1), (Platform::SchipModern, 1, 0)]
+        {
+            let cpu = draw.clone().platform(platform).run(3).unwrap();
+            assert_eq!(cpu.registers()[0xF], collided, "{}", platform);
+            let cpu = draw.clone().platform(platform).run(4).unwrap();
+            assert_eq!(cpu.registers()[0xF], clipped, "{}", platform);
+        }
+    }
+
+    #[test]
+    fn scrolling() {
+        // in high resolution, draw a pixel at (8, 8) and scroll it
+        let scroll = |instr| {
+            let cpu = CpuBuilder::new()
+                .memory(0x300, &[0x80])
+                .index(0x300)
+                .register(0, 8)
+                .instructions(&[0x00FF, 0xD001, instr])
+                .platform(Platform::SchipModern)
+                .run(3)
+                .unwrap();
+            lit(&cpu)
+        };
+        // 00C2 - down 2 rows, 00FB - right 4 pixels, 00FC - left 4 pixels
+        assert_eq!(scroll(0x00C2), [(8, 10)]);
+        assert_eq!(scroll(0x00FB), [(12, 8)]);
+        assert_eq!(scroll(0x00FC), [(4, 8)]);
+    }
+
+    #[test]
+    fn switch_resolution() {
+        // 00FF - high resolution, 00FE - low resolution, clearing the screen each time
+        let switch = CpuBuilder::new()
+            .memory(0x300, &[0x80])
+            .index(0x300)
+            .instructions(&[0x00FF, 0xD001, 0x00FE])
+            .platform(Platform::SchipModern);
+        let cpu = switch.clone().run(2).unwrap();
+        assert_eq!((cpu.resolution(), lit(&cpu)), ((128, 64), vec![(0, 0)]));
+        let cpu = switch.run(3).unwrap();
+        assert_eq!((cpu.resolution(), lit(&cpu)), ((64, 32), vec![]));
+    }
+
+    #[test]
+    fn draw_16x16() {
+        // DXY0 - in high resolution, draw a 16x16 sprite from 32 bytes, two for each row
+        let mut sprite = [0u8; 32];
+        sprite[0] = 0x80;
+        sprite[31] = 0x01;
+        let draw = CpuBuilder::new()
+            .memory(0x300, &sprite)
+            .index(0x300)
+            .register(0, 4)
+            .instructions(&[0x00FF, 0xD000, 0xD000])
+            .platform(Platform::SchipModern);
+        let cpu = draw.clone().run(2).unwrap();
+        assert_eq!(lit(&cpu), [(4, 4), (19, 19)]);
+        assert_eq!(cpu.registers()[0xF], 0);
+        let cpu = draw.run(3).unwrap();
+        assert_eq!(lit(&cpu), []);
+        assert_eq!(cpu.registers()[0xF], 1);
+    }
+
+    #[test]
+    fn user_flags() {
+        // F275 - save V0 to V2 in the user flags, F285 - restore them
+        let cpu = CpuBuilder::new()
+            .registers([1, 2, 3, 4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0])
+            .instructions(&[0xF275, 0x6000, 0x6100, 0x6200, 0x6300, 0xF285])
+            .platform(Platform::SchipModern)
+            .run(6)
+            .unwrap();
+        assert_eq!(cpu.registers()[..4], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn store_load_range() {
+        // 5132 - store V1 to V3, 5312 - the same in reverse order
+        let store = CpuBuilder::new()
+            .registers([1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16])
+            .index(0x300)
+            .platform(Platform::XoChip);
+        for (instr, stored) in [(0x5132, [2, 3, 4]), (0x5312, [4, 3, 2])] {
+            let cpu = store.clone().instructions(&[instr]).run(1).unwrap();
+            assert_eq!(cpu.read_memory(0x300, 3).unwrap(), stored);
+            assert_eq!(cpu.index(), 0x300);
+        }
+
+        // 5133 - load V1 to V3, 5313 - the same in reverse order
+        let load = CpuBuilder::new()
+            .memory(0x300, &[9, 8, 7])
+            .index(0x300)
+            .platform(Platform::XoChip);
+        for (instr, loaded) in [(0x5133, [9, 8, 7]), (0x5313, [7, 8, 9])] {
+            let cpu = load.clone().instructions(&[instr]).run(1).unwrap();
+            assert_eq!(cpu.registers()[1..4], loaded);
+            assert_eq!(cpu.index(), 0x300);
+        }
+    }
+
+    #[test]
+    fn draw_planes() {
+        // FN01 - select the planes to draw to. With both selected, DXYN draws N rows to the
+        // first plane, then the next N rows to the second
+        let draw = |planes| {
+            CpuBuilder::new()
+                .memory(0x300, &[0xC0, 0x80])
+                .index(0x300)
+                .instructions(&[planes, 0xD001, 0xD001])
+                .platform(Platform::XoChip)
+        };
+        let pixels = |cpu: &CPU| [cpu.framebuffer().get(0, 0), cpu.framebuffer().get(1, 0)];
+
+        let cpu = draw(0xF101).run(2).unwrap();
+        assert_eq!(pixels(&cpu), [0b01, 0b01]);
+        let cpu = draw(0xF201).run(2).unwrap();
+        assert_eq!(pixels(&cpu), [0b10, 0b10]);
+        let cpu = draw(0xF301).run(2).unwrap();
+        assert_eq!(pixels(&cpu), [0b11, 0b01]);
+        assert_eq!(cpu.registers()[0xF], 0);
+
+        // a collision in either plane sets VF
+        let cpu = draw(0xF301).run(3).unwrap();
+        assert_eq!(pixels(&cpu), [0, 0]);
+        assert_eq!(cpu.registers()[0xF], 1);
+    }
+
+    #[test]
+    fn load_index_24() {
+        // 01NN NNNN - load a 24-bit address, which a skip steps over as a whole
+        let cpu = run(
+            Platform::MegaChip,
+            CpuBuilder::new().instructions(&[0x0101, 0x2345]),
+        );
+        assert_eq!((cpu.index(), cpu.pc()), (0x01_2345, 0x204));
+        let cpu = CpuBuilder::new()
+            .instructions(&[0x3000, 0x0101, 0x2345])
+            .platform(Platform::MegaChip)
+            .run(1)
+            .unwrap();
+        assert_eq!(cpu.pc(), 0x206);
+    }
+
+    // draw a 2x1 sprite of palette colours 1 and 2 at the top left of the MegaChip screen
+    // `times` times, with the blend mode and collision colour set up by `setup`
+    fn mega_draw(setup: &[u16], times: usize) -> Result<CPU, CpuError> {
+        // 0011 - MegaChip mode on, 0202 - load 2 palette colours, 0302/0401 - 2x1 sprites
+        let mut program = vec![0x0011, 0xA400, 0x0202, 0x0302, 0x0401, 0xA300];
+        program.extend(setup);
+        program.extend(std::iter::repeat_n(0xD000, times));
+        program.push(0x00E0);
+        CpuBuilder::new()
+            .memory(0x300, &[1, 2])
+            .memory(0x400, &[0xFF, 0x80, 0, 0, 0xFF, 0, 0x40, 0])
+            .instructions(&program)
+            .platform(Platform::MegaChip)
+            .run(program.len())
+    }
+
+    // the first two pixels shown on the MegaChip screen
+    fn mega_pixels(cpu: &CPU) -> [u8; 8] {
+        let mut frame = vec![0; 256 * 192 * 4];
+        cpu.draw(&mut frame);
+        frame[..8].try_into().unwrap()
+    }
+
+    #[test]
+    fn mega_palette_sprites() {
+        let cpu = mega_draw(&[], 1).unwrap();
+        assert_eq!(cpu.resolution(), (256, 192));
+        assert_eq!(mega_pixels(&cpu), [0x80, 0, 0, 0xFF, 0, 0x40, 0, 0xFF]);
+    }
+
+    #[test]
+    fn mega_blend_modes() {
+        // 0802 - 50% opacity, over black
+        let cpu = mega_draw(&[0x0802], 1).unwrap();
+        assert_eq!(mega_pixels(&cpu), [0x40, 0, 0, 0xFF, 0, 0x20, 0, 0xFF]);
+        // 0804 - additive, which saturates
+        let cpu = mega_draw(&[0x0804], 2).unwrap();
+        assert_eq!(mega_pixels(&cpu), [0xFF, 0, 0, 0xFF, 0, 0x80, 0, 0xFF]);
+        // 0805 - multiply, which keeps black where the colours don't overlap
+        let cpu = mega_draw(&[0x0805], 2).unwrap();
+        assert_eq!(mega_pixels(&cpu), [0, 0, 0, 0xFF, 0, 0, 0, 0xFF]);
+        // 0806 isn't a blend mode
+        assert!(matches!(
+            mega_draw(&[0x0806], 1),
+            Err(CpuError::InvalidOpcode { instr: 0x0806, .. })
+        ));
+    }
+
+    #[test]
+    fn mega_collision_colour() {
+        // no collisions are detected until 09NN picks a colour to collide with
+        let cpu = mega_draw(&[], 2).unwrap();
+        assert_eq!(cpu.registers()[0xF], 0);
+        let cpu = mega_draw(&[0x0902], 1).unwrap();
+        assert_eq!(cpu.registers()[0xF], 0);
+        let cpu = mega_draw(&[0x0902], 2).unwrap();
+        assert_eq!(cpu.registers()[0xF], 1);
+    }
+
+    #[test]
+    fn chip8x_colours() {
+        // draw a pixel in the top left of the first two zones, and one on the row below
+        let colours = |setup: &[u16]| {
+            let mut program = setup.to_vec();
+            program.extend([0xA400, 0xD341, 0x6308, 0xD341, 0x6300, 0x6401, 0xD341]);
+            let cpu = CpuBuilder::new()
+                .memory(0x400, &[0x80])
+                .instructions(&program)
+                .platform(Platform::Chip8X)
+                .run(program.len())
+                .unwrap();
+            let mut frame = vec![0; 64 * 32 * 4];
+            cpu.draw(&mut frame);
+            let pixel =
+                |x: usize, y: usize| <[u8; 4]>::try_from(&frame[(y * 64 + x) * 4..][..4]).unwrap();
+            [pixel(0, 0), pixel(8, 0), pixel(0, 1), pixel(1, 0)]
+        };
+        let (red, blue) = ([0xFF, 0, 0, 0xFF], [0, 0, 0xFF, 0xFF]);
+        let (dark_blue, black) = ([0, 0, 0x80, 0xFF], [0, 0, 0, 0xFF]);
+
+        // zones start out red, on a dark blue background
+        assert_eq!(colours(&[]), [red, red, red, dark_blue]);
+        // B021 - colour zone columns 0 to 1 of row 0 blue, with V0 = 0x10, V1 = 2 and V2 = 0
+        let zones = [0x6010, 0x6102, 0x6200, 0xB021];
+        assert_eq!(colours(&zones), [blue, blue, red, dark_blue]);
+        // B020 - the same for the first 4 rows of zones
+        let zones = [0x6010, 0x6102, 0x6200, 0xB020];
+        assert_eq!(colours(&zones), [blue, blue, blue, dark_blue]);
+        // 02A0 - the next background colour
+        assert_eq!(colours(&[0x02A0]), [red, red, red, black]);
+    }
+
+    #[test]
+    fn chip8x_second_keypad() {
+        // E0F2 - skip if the key in V0 is down on the second keypad, E0F5 - skip if it isn't
+        let mut down = [[false; 16]; 2];
+        down[1][5] = true;
+        let up = [[false; 16]; 2];
+        for (keypad, skip_down, skip_up) in [(down, 0x304, 0x302), (up, 0x302, 0x304)] {
+            let keys = CpuBuilder::new().register(0, 5).keypad(keypad);
+            let cpu = run(Platform::Chip8X, keys.clone().instructions(&[0xE0F2]));
+            assert_eq!(cpu.pc(), skip_down);
+            let cpu = run(Platform::Chip8X, keys.instructions(&[0xE0F5]));
+            assert_eq!(cpu.pc(), skip_up);
+        }
+    }
+
+    // a port that records what's sent to it, and replies with queued values
+    #[derive(Default, Clone)]
+    struct StubPort {
+        sent: Arc<Mutex<Vec<u8>>>,
+        replies: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl PortHandler for StubPort {
+        fn output(&mut self, value: u8) {
+            self.sent.lock().unwrap().push(value);
+        }
+
+        fn input(&mut self) -> Option<u8> {
+            self.replies.lock().unwrap().pop()
+        }
+    }
+
+    #[test]
+    fn chip8x_port() {
+        // F1F8 - send V1 to the port, F2FB - wait for a value from it in V2
+        let port = StubPort::default();
+        let mut cpu = CpuBuilder::new()
+            .register(1, 0x42)
+            .instructions(&[0xF1F8, 0xF2FB])
+            .platform(Platform::Chip8X)
+            .build()
+            .unwrap();
+        cpu.set_port_handler(Box::new(port.clone()));
+        cpu.run_instructions(3).unwrap();
+        assert_eq!(*port.sent.lock().unwrap(), [0x42]);
+        assert_eq!(cpu.pc(), 0x302);
+        port.replies.lock().unwrap().push(0x99);
+        cpu.run_instructions(1).unwrap();
+        assert_eq!((cpu.pc(), cpu.registers()[2]), (0x304, 0x99));
+    }
+
+    #[test]
+    fn eti660_display() {
+        // the display is 64x48, so a sprite drawn at y = 48 wraps to the top
+        let cpu = CpuBuilder::new()
+            .memory(0x700, &[0x80])
+            .index(0x700)
+            .register(1, 48)
+            .instructions(&[0xD011])
+            .platform(Platform::Eti660)
+            .run(1)
+            .unwrap();
+        assert_eq!(cpu.resolution(), (64, 48));
+        assert_eq!(lit(&cpu), [(0, 0)]);
+    }
+}
@@ -0,0 +1,87 @@
+use std::fmt::Debug;
+
+// the background colours that 02A0 cycles through, in order: blue, black, green, red
+const BACKGROUNDS: [[u8; 4]; 4] = [
+    [0x00, 0x00, 0x80, 0xFF],
+    [0x00, 0x00, 0x00, 0xFF],
+    [0x00, 0x80, 0x00, 0xFF],
+    [0x80, 0x00, 0x00, 0xFF],
+];
+
+// the foreground colours that can be given to a zone: black, red, blue, violet, green, yellow, aqua, white
+const FOREGROUNDS: [[u8; 4]; 8] = [
+    [0x00, 0x00, 0x00, 0xFF],
+    [0xFF, 0x00, 0x00, 0xFF],
+    [0x00, 0x00, 0xFF, 0xFF],
+    [0xFF, 0x00, 0xFF, 0xFF],
+    [0x00, 0xFF, 0x00, 0xFF],
+    [0xFF, 0xFF, 0x00, 0xFF],
+    [0x00, 0xFF, 0xFF, 0xFF],
+    [0xFF, 0xFF, 0xFF, 0xFF],
+];
+
+// the zones are 8 pixels wide, and the colour can be set for every row of pixels
+const ZONE_COLUMNS: usize = 8;
+const ZONE_ROWS: usize = 32;
+
+/// Something connected to the CHIP-8X I/O port, used by FXF8 and FXFB
+pub trait PortHandler: Send {
+    /// Called with the value a program sends to the port
+    fn output(&mut self, value: u8);
+    /// The next value the port has for the program, or `None` to keep it waiting
+    fn input(&mut self) -> Option<u8>;
+}
+
+// the port handler, if one has been connected
+#[derive(Default)]
+pub(crate) struct Port(pub Option<Box<dyn PortHandler>>);
+
+impl Debug for Port {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0 {
+            Some(_) => write!(f, "Port(connected)"),
+            None => write!(f, "Port(disconnected)"),
+        }
+    }
+}
+
+/// The CHIP-8X colour state: a background colour for the whole screen, and a foreground colour
+/// for each 8 pixel wide zone of each row
+#[derive(Debug, Clone)]
+pub(crate) struct ColourZones {
+    background: usize,
+    zones: [[u8; ZONE_COLUMNS]; ZONE_ROWS],
+}
+
+impl ColourZones {
+    pub fn new() -> Self {
+        Self {
+            background: 0,
+            // zones start out red
+            zones: [[1; ZONE_COLUMNS]; ZONE_ROWS],
+        }
+    }
+
+    /// 02A0, move on to the next background colour
+    pub fn cycle_background(&mut self) {
+        self.background = (self.background + 1) % BACKGROUNDS.len();
+    }
+
+    /// BXYN, colour the zones in the columns `left..=right` and pixel rows `top..=bottom`
+    pub fn fill(&mut self, left: usize, right: usize, top: usize, bottom: usize, colour: u8) {
+        for row in self.zones.iter_mut().take(bottom + 1).skip(top) {
+            for zone in row.iter_mut().take(right + 1).skip(left) {
+                *zone = colour & 0b111;
+            }
+        }
+    }
+
+    /// The colour of the pixel at (x, y) on a 64x32 display
+    pub fn colour(&self, x: usize, y: usize, on: bool) -> [u8; 4] {
+        if on {
+            FOREGROUNDS[self.zones[y % ZONE_ROWS][(x / 8) % ZONE_COLUMNS] as usize]
+        } else {
+            BACKGROUNDS[self.background]
+        }
+    }
+}
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+/// How many times a second the timers are decremented, and the display refreshed
+pub const FRAMES_PER_SECOND: u64 = 60;
+
+/// Emulated time, counted in cycles.
+///
+/// A frame is a fixed number of cycles, and the 60Hz timers tick each time one passes, so a
+/// program behaves the same however fast or slow the emulator is really running. With the
+/// simple timing model every instruction takes one cycle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Clock {
+    cycles_per_frame: u64,
+    cycles: u64,
+    frames: u64,
+    // cycles since the start of the current frame
+    frame_cycles: u64,
+}
+
+impl Clock {
+    pub fn new(cycles_per_frame: u64) -> Self {
+        Self {
+            cycles_per_frame: cycles_per_frame.max(1),
+            cycles: 0,
+            frames: 0,
+            frame_cycles: 0,
+        }
+    }
+
+    /// Cycles since the start
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Frames since the start, which is how many times the timers have ticked
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn cycles_per_frame(&self) -> u64 {
+        self.cycles_per_frame
+    }
+
+    /// Change the speed of the emulated machine, which takes effect from the current frame
+    pub fn set_cycles_per_frame(&mut self, cycles_per_frame: u64) {
+        self.cycles_per_frame = cycles_per_frame.max(1);
+        self.frame_cycles = self.frame_cycles.min(self.cycles_per_frame - 1);
+    }
+
+    /// Cycles left before the next frame starts
+    pub fn cycles_until_frame(&self) -> u64 {
+        self.cycles_per_frame - self.frame_cycles
+    }
+
+    /// The emulated time since the start
+    pub fn elapsed(&self) -> Duration {
+        let nanos = (self.frames as u128 * self.cycles_per_frame as u128
+            + self.frame_cycles as u128)
+            * 1_000_000_000
+            / (self.cycles_per_frame as u128 * FRAMES_PER_SECOND as u128);
+        Duration::from_nanos(nanos as u64)
+    }
+
+    /// How many cycles it takes to reach `elapsed` emulated time from the start, if the speed hasn't changed
+    pub fn cycles_at(&self, elapsed: Duration) -> u64 {
+        (elapsed.as_nanos() * self.cycles_per_frame as u128 * FRAMES_PER_SECOND as u128
+            / 1_000_000_000) as u64
+    }
+
+    // move time on by `cycles`, returning how many frames have started
+    pub(crate) fn advance(&mut self, cycles: u64) -> u64 {
+        self.cycles += cycles;
+        self.frame_cycles += cycles;
+        let frames = self.frame_cycles / self.cycles_per_frame;
+        self.frame_cycles %= self.cycles_per_frame;
+        self.frames += frames;
+        frames
+    }
+}
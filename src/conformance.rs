@@ -0,0 +1,411 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::{
+    cpu::CPU,
+    error::CpuError,
+    framebuffer::Framebuffer,
+    headless::{run_headless, KeyScript},
+    platform::Platform,
+    random::RandomSource,
+};
+
+/// One screen from the Timendus CHIP-8 test suite, run headless on each of its platforms
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Case {
+    pub name: &'static str,
+    /// the ROM's file name in the suite
+    pub rom: &'static str,
+    pub platforms: &'static [Platform],
+    /// written to 0x1FF before starting, which is how the suite picks a test without going through its menu
+    pub preset: Option<u8>,
+    /// how long to run before checking the screen
+    pub frames: u64,
+    /// keys to press, in the format `KeyScript` reads
+    pub keys: &'static str,
+}
+
+// the platforms that can run a plain CHIP-8 program loaded at 0x200
+const CHIP8_PLATFORMS: &[Platform] = &[
+    Platform::CosmacVip,
+    Platform::Chip48,
+    Platform::Schip11,
+    Platform::SchipModern,
+    Platform::XoChip,
+];
+const SCHIP_PLATFORMS: &[Platform] = &[Platform::Schip11, Platform::SchipModern];
+
+/// The test screens from the Timendus suite that can be checked from the display alone
+pub const CASES: &[Case] = &[
+    Case {
+        name: "chip8-logo",
+        rom: "1-chip8-logo.ch8",
+        platforms: CHIP8_PLATFORMS,
+        preset: None,
+        frames: 60,
+        keys: "",
+    },
+    Case {
+        name: "ibm-logo",
+        rom: "2-ibm-logo.ch8",
+        platforms: CHIP8_PLATFORMS,
+        preset: None,
+        frames: 60,
+        keys: "",
+    },
+    Case {
+        name: "corax+",
+        rom: "3-corax+.ch8",
+        platforms: CHIP8_PLATFORMS,
+        preset: None,
+        frames: 60,
+        keys: "",
+    },
+    Case {
+        name: "flags",
+        rom: "4-flags.ch8",
+        platforms: CHIP8_PLATFORMS,
+        preset: None,
+        frames: 120,
+        keys: "",
+    },
+    Case {
+        name: "quirks-chip8",
+        rom: "5-quirks.ch8",
+        platforms: &[Platform::CosmacVip],
+        preset: Some(1),
+        frames: 600,
+        keys: "",
+    },
+    Case {
+        name: "quirks-schip",
+        rom: "5-quirks.ch8",
+        platforms: SCHIP_PLATFORMS,
+        preset: Some(2),
+        frames: 600,
+        keys: "",
+    },
+    Case {
+        name: "quirks-xochip",
+        rom: "5-quirks.ch8",
+        platforms: &[Platform::XoChip],
+        preset: Some(3),
+        frames: 600,
+        keys: "",
+    },
+    Case {
+        name: "keypad-down",
+        rom: "6-keypad.ch8",
+        platforms: CHIP8_PLATFORMS,
+        preset: Some(1),
+        frames: 60,
+        keys: "30+5",
+    },
+    Case {
+        name: "keypad-up",
+        rom: "6-keypad.ch8",
+        platforms: CHIP8_PLATFORMS,
+        preset: Some(2),
+        frames: 60,
+        keys: "30+5",
+    },
+    Case {
+        name: "keypad-getkey",
+        rom: "6-keypad.ch8",
+        platforms: CHIP8_PLATFORMS,
+        preset: Some(3),
+        frames: 90,
+        keys: "30+5,40-5",
+    },
+    Case {
+        name: "scrolling-schip-lores",
+        rom: "8-scrolling.ch8",
+        platforms: SCHIP_PLATFORMS,
+        preset: Some(1),
+        frames: 120,
+        keys: "",
+    },
+    Case {
+        name: "scrolling-schip-hires",
+        rom: "8-scrolling.ch8",
+        platforms: SCHIP_PLATFORMS,
+        preset: Some(2),
+        frames: 120,
+        keys: "",
+    },
+    Case {
+        name: "scrolling-xochip-lores",
+        rom: "8-scrolling.ch8",
+        platforms: &[Platform::XoChip],
+        preset: Some(3),
+        frames: 120,
+        keys: "",
+    },
+    Case {
+        name: "scrolling-xochip-hires",
+        rom: "8-scrolling.ch8",
+        platforms: &[Platform::XoChip],
+        preset: Some(4),
+        frames: 120,
+        keys: "",
+    },
+];
+
+/// A hash of everything on the display, which stays the same between builds and machines
+pub fn framebuffer_hash(display: &Framebuffer) -> u64 {
+    // 64-bit FNV-1a
+    let mut hash = 0xCBF2_9CE4_8422_2325u64;
+    let (width, height) = display.size();
+    let size = [width as u32, height as u32].map(u32::to_le_bytes);
+    let pixels = display.pixels().map(|(_, _, px)| px);
+    for byte in size.into_iter().flatten().chain(pixels) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01B3);
+    }
+
+    hash
+}
+
+/// The hashes of the screens each case should end on, on each platform.
+///
+/// Read from and written as lines of `CASE PLATFORM HASH`, with the hash in hex. Blank lines
+/// and lines starting with `#` are ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Goldens {
+    // keyed by case and platform name, so they're written out in order
+    hashes: BTreeMap<(String, &'static str), u64>,
+}
+
+impl Goldens {
+    pub fn get(&self, case: &str, platform: Platform) -> Option<u64> {
+        self.hashes
+            .get(&(case.to_string(), platform.name()))
+            .copied()
+    }
+
+    pub fn set(&mut self, case: &str, platform: Platform, hash: u64) {
+        self.hashes
+            .insert((case.to_string(), platform.name()), hash);
+    }
+}
+
+impl FromStr for Goldens {
+    type Err = InvalidGolden;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut goldens = Goldens::default();
+        for (n, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = || InvalidGolden(n + 1);
+            let mut parts = line.split_whitespace();
+            let case = parts.next().ok_or_else(invalid)?;
+            let platform = parts.next().and_then(|p| p.parse().ok());
+            let hash = parts.next().and_then(|h| u64::from_str_radix(h, 16).ok());
+            match (platform, hash, parts.next()) {
+                (Some(platform), Some(hash), None) => goldens.set(case, platform, hash),
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(goldens)
+    }
+}
+
+impl Display for Goldens {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for ((case, platform), hash) in &self.hashes {
+            writeln!(f, "{} {} {:016x}", case, platform, hash)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Returned when a line of a golden file can't be read, holding the line number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvalidGolden(pub usize);
+
+impl Display for InvalidGolden {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "line {} should be a test name, a platform and a hash",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidGolden {}
+
+/// How a case went on one platform
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// the screen matched the golden hash
+    Pass,
+    /// the screen didn't match the golden hash
+    Fail { expected: u64 },
+    /// there's no golden hash to compare the screen with
+    Unknown,
+    /// the ROM isn't there, so the case wasn't run
+    Skipped { missing: PathBuf },
+    /// the program stopped with an error before the screen could be checked
+    Error(CpuError),
+}
+
+/// The result of running one case on one platform
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub case: &'static str,
+    pub platform: Platform,
+    pub outcome: Outcome,
+    /// the hash of the screen the case ended on, if it got that far
+    pub hash: Option<u64>,
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = format!("{} on {}", self.case, self.platform);
+        let hash = self.hash.unwrap_or_default();
+        match &self.outcome {
+            Outcome::Pass => write!(f, "PASS  {}", name),
+            Outcome::Fail { expected } => write!(
+                f,
+                "FAIL  {}: expected {:016x}, got {:016x}",
+                name, expected, hash
+            ),
+            Outcome::Unknown => write!(f, "NEW   {}: {:016x} has no golden hash", name, hash),
+            Outcome::Skipped { missing } => {
+                write!(f, "SKIP  {}: {} not found", name, missing.display())
+            }
+            Outcome::Error(e) => write!(f, "ERROR {}: {}", name, e),
+        }
+    }
+}
+
+/// Run one case on `platform`, with the suite's ROMs in `rom_dir`.
+///
+/// `on_screen` is given the CPU once the case has run, to save the screen it ended on.
+pub fn run_case(
+    case: &Case,
+    platform: Platform,
+    rom_dir: &Path,
+    goldens: &Goldens,
+    on_screen: &mut impl FnMut(&Report, &CPU),
+) -> Report {
+    let mut report = Report {
+        case: case.name,
+        platform,
+        outcome: Outcome::Unknown,
+        hash: None,
+    };
+    let path = rom_dir.join(case.rom);
+    let Ok(rom) = std::fs::read(&path) else {
+        report.outcome = Outcome::Skipped { missing: path };
+        return report;
+    };
+
+    let mut cpu = CPU::with_platform(platform);
+    // the same numbers every time, so the screens are too
+    cpu.set_random_source(RandomSource::seeded(0));
+    let keys = case
+        .keys
+        .parse::<KeyScript>()
+        .expect("conformance cases have valid key scripts");
+    let run = cpu
+        .load_program(&rom)
+        .and_then(|_| match case.preset {
+            Some(preset) => cpu.write_memory(0x1FF, &[preset]),
+            None => Ok(()),
+        })
+        .and_then(|_| run_headless(&mut cpu, case.frames, &keys, |_, _| {}));
+
+    match run {
+        Ok(_) => {
+            let hash = framebuffer_hash(cpu.framebuffer());
+            report.hash = Some(hash);
+            report.outcome = match goldens.get(case.name, platform) {
+                Some(expected) if expected == hash => Outcome::Pass,
+                Some(expected) => Outcome::Fail { expected },
+                None => Outcome::Unknown,
+            };
+        }
+        Err(e) => report.outcome = Outcome::Error(e),
+    }
+    on_screen(&report, &cpu);
+
+    report
+}
+
+/// Run every case on every platform it's for
+pub fn run_suite(
+    rom_dir: &Path,
+    goldens: &Goldens,
+    mut on_screen: impl FnMut(&Report, &CPU),
+) -> Vec<Report> {
+    let mut reports = vec![];
+    for case in CASES {
+        for platform in case.platforms {
+            reports.push(run_case(case, *platform, rom_dir, goldens, &mut on_screen));
+        }
+    }
+
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn goldens_round_trip() {
+        let mut goldens = Goldens::default();
+        goldens.set("ibm-logo", Platform::CosmacVip, 0x1234);
+        goldens.set("flags", Platform::XoChip, u64::MAX);
+        let text = format!("# a comment\n\n{}", goldens);
+        assert_eq!(text.parse::<Goldens>(), Ok(goldens));
+
+        assert_eq!("flags xochip".parse::<Goldens>(), Err(InvalidGolden(1)));
+        assert_eq!(
+            "\nflags nowhere 12".parse::<Goldens>(),
+            Err(InvalidGolden(2))
+        );
+    }
+
+    #[test]
+    fn outcomes() {
+        let case = Case {
+            name: "logo",
+            rom: "logo.ch8",
+            platforms: &[Platform::CosmacVip],
+            preset: None,
+            frames: 2,
+            keys: "",
+        };
+        let dir = std::env::temp_dir().join(format!("potato-conformance-{}", std::process::id()));
+        let run =
+            |goldens: &Goldens| run_case(&case, Platform::CosmacVip, &dir, goldens, &mut |_, _| {});
+        let mut goldens = Goldens::default();
+        assert!(matches!(run(&goldens).outcome, Outcome::Skipped { .. }));
+
+        // draw the 0 glyph, then loop forever
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(case.rom), [0xA0, 0x50, 0xD0, 0x05, 0x12, 0x04]).unwrap();
+        let report = run(&goldens);
+        let hash = report.hash.unwrap();
+        assert_eq!(report.outcome, Outcome::Unknown);
+
+        goldens.set(case.name, Platform::CosmacVip, hash);
+        assert_eq!(run(&goldens).outcome, Outcome::Pass);
+        goldens.set(case.name, Platform::CosmacVip, !hash);
+        assert_eq!(run(&goldens).outcome, Outcome::Fail { expected: !hash });
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
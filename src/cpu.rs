@@ -1,6 +1,23 @@
 use phf::{phf_map, Map};
 use std::fmt::Display;
 
+use crate::{
+    audio::{Audio, AudioSink},
+    chip8x::{ColourZones, Port, PortHandler},
+    clock::Clock,
+    error::{CpuError, FrameResult, StepOutcome},
+    font::{Font, BIG_FONT_START, FONT_START},
+    framebuffer::Framebuffer,
+    instruction::{Instruction, InstructionSet},
+    megachip::{BlendMode, MegaChip, Sample, MEGA_HEIGHT, MEGA_WIDTH},
+    memory::MemoryMap,
+    platform::{Platform, Profile},
+    quirks::{LoadStore, Quirks},
+    random::RandomSource,
+    state::CpuState,
+    timing::Timing,
+};
+
 pub const DEFAULT_KEYPAD: Map<u32, usize> = phf_map! {
     2u32 => 0x1,
     3u32 => 0x2,
@@ -20,98 +37,151 @@ pub const DEFAULT_KEYPAD: Map<u32, usize> = phf_map! {
     47u32 => 0xF
 };
 
-// const FONT: [u8; 80] = [
-//     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-//     0x20, 0x60, 0x20, 0x20, 0x70, // 1
-//     0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-//     0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-//     0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-//     0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-//     0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-//     0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-//     0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-//     0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-//     0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-//     0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-//     0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-//     0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-//     0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-//     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-// ];
-const FONT: [u8; 80] = [
-    0b11100000, 0b10100000, 0b10100000, 0b10100000, 0b11100000, //0
-    0b01000000, 0b01000000, 0b01000000, 0b01000000, 0b01000000, //1
-    0b11100000, 0b00100000, 0b11100000, 0b10000000, 0b11100000, //2
-    0b11100000, 0b00100000, 0b11100000, 0b00100000, 0b11100000, //3
-    0b10000000, 0b10100000, 0b10100000, 0b11100000, 0b00100000, //4
-    0b11100000, 0b10000000, 0b11100000, 0b00100000, 0b11100000, //5
-    0b11100000, 0b10000000, 0b11100000, 0b10100000, 0b11100000, //6
-    0b11100000, 0b00100000, 0b00100000, 0b00100000, 0b00100000, //7
-    0b11100000, 0b10100000, 0b11100000, 0b10100000, 0b11100000, //8
-    0b11100000, 0b10100000, 0b11100000, 0b00100000, 0b11100000, //9
-    0b11100000, 0b10100000, 0b11100000, 0b10100000, 0b10100000, //A
-    0b11000000, 0b10100000, 0b11100000, 0b10100000, 0b11000000, //B
-    0b11100000, 0b10000000, 0b10000000, 0b10000000, 0b11100000, //C
-    0b11000000, 0b10100000, 0b10100000, 0b10100000, 0b11000000, //D
-    0b11100000, 0b10000000, 0b11100000, 0b10000000, 0b11100000, //E
-    0b11100000, 0b10000000, 0b11000000, 0b10000000, 0b10000000, //F
-];
-const FONT_START: u16 = 0x050;
+/// The keys for the CHIP-8X second keypad, laid out like the first one on the right hand side of the keyboard
+pub const SECOND_KEYPAD: Map<u32, usize> = phf_map! {
+    8u32 => 0x1,
+    9u32 => 0x2,
+    10u32 => 0x3,
+    11u32 => 0xC,
+    22u32 => 0x4,
+    23u32 => 0x5,
+    24u32 => 0x6,
+    25u32 => 0xD,
+    36u32 => 0x7,
+    37u32 => 0x8,
+    38u32 => 0x9,
+    39u32 => 0xE,
+    50u32 => 0xA,
+    51u32 => 0x0,
+    52u32 => 0xB,
+    53u32 => 0xF
+};
 
 pub const WIDTH: usize = 64;
 pub const HEIGHT: usize = 32;
+/// The height of the display used by programs for the two-page hi-res CHIP-8 interpreter
+pub const TWO_PAGE_HEIGHT: usize = 64;
+/// The size of the SCHIP high resolution display
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+/// The smallest address space a CPU can have, which holds the interpreter's page and the fonts
+pub const MIN_MEMORY_SIZE: usize = 0x200;
+
+// the colours for each combination of XO-CHIP bitplanes, indexed by the planes that are set
+const PALETTE: [[u8; 4]; 4] = [
+    [0x50, 0x50, 0x50, 0xFF],
+    [0xF0, 0x90, 0xF0, 0xFF],
+    [0x60, 0xB0, 0xF0, 0xFF],
+    [0xFF, 0xFF, 0xFF, 0xFF],
+];
 
 #[derive(Debug)]
 pub struct CPU {
-    mem: [u8; 4096],
+    mem: Vec<u8>,
+    map: MemoryMap,
     pc: usize,
-    index: u16,
+    // 16 bits is enough for everything except MegaChip, which has 24-bit addresses
+    index: u32,
     stack: Stack,
     registers: [u8; 16],
     delay_timer: u8,
     sound_timer: u8,
-    pub keypad: [bool; 16],
-    display: Vec<Vec<bool>>,
+    /// the state of each key on the keypad, and on the second keypad that only CHIP-8X uses
+    pub keypad: [[bool; 16]; 2],
+    pub quirks: Quirks,
+    instruction_set: InstructionSet,
+    // each pixel holds a bit for every plane it's set in; only XO-CHIP uses more than the first plane
+    display: Framebuffer,
+    // the XO-CHIP bitplanes selected by FN01
+    planes: u8,
+    // the XO-CHIP audio pattern buffer and the pitch it's played back at
+    pattern: [u8; 16],
+    pitch: u8,
+    // the MegaChip palette, sprite settings and colour screen, only present for the MegaChip instruction set
+    megachip: Option<Box<MegaChip>>,
+    // whether MegaChip mode has been turned on with 0011
+    mega_mode: bool,
+    // the CHIP-8X colours, only present for the CHIP-8X instruction set
+    colours: Option<ColourZones>,
+    // whatever is connected to the CHIP-8X I/O port
+    port: Port,
+    // where the sound goes, and whether the sound timer has the beeper on
+    audio: Audio,
+    beeping: bool,
+    // the resolution used when the display isn't in SCHIP high resolution mode
+    lores: (usize, usize),
+    hires: bool,
+    // whether the program is for the two-page hi-res interpreter, where 0230 clears the screen
+    two_page: bool,
+    // the SCHIP RPL user flags, saved and loaded with FX75/FX85
+    flags: [u8; 16],
+    // set once 00FD has been run
+    halted: bool,
+    // set by a draw with the display wait quirk, to skip the rest of the frame
+    frame_done: bool,
+    // set while FX0A is waiting for a key
+    waiting_for_key: bool,
+    // the keys FX0A saw held down the last time it ran, and the key it's waiting to be released
+    held_keys: [bool; 16],
+    wait_key: Option<u8>,
+    // the keypad at the last frame boundary, and the keys that changed during the frame before it
+    frame_keypad: [[bool; 16]; 2],
+    pressed: [[bool; 16]; 2],
+    released: [[bool; 16]; 2],
+    // the keys set_key has seen go down and come up since the last frame boundary
+    pressing: [[bool; 16]; 2],
+    releasing: [[bool; 16]; 2],
+    rng: RandomSource,
+    clock: Clock,
+    timing: Timing,
 }
 
-#[derive(Debug)]
+// the return addresses of the subroutines that have been called, most recent last
+#[derive(Debug, Clone)]
 struct Stack {
-    mem: [u16; 256],
-    sp: u8,
+    addrs: Vec<usize>,
+    // None lets the stack grow without limit
+    depth: Option<usize>,
 }
 
 impl Stack {
-    pub fn new() -> Self {
+    pub fn new(depth: Option<usize>) -> Self {
         Self {
-            mem: [0u16; 256],
-            sp: 0,
+            addrs: Vec::with_capacity(depth.unwrap_or(16)),
+            depth,
         }
     }
 
-    pub fn push(&mut self, addr: u16) {
-        self.sp += 1;
-        self.mem[self.sp as usize] = addr;
+    pub fn push(&mut self, addr: usize) -> Result<(), CpuError> {
+        if self.depth.is_some_and(|d| self.addrs.len() >= d) {
+            return Err(CpuError::StackOverflow);
+        }
+        self.addrs.push(addr);
+        Ok(())
     }
 
-    pub fn pop(&mut self) -> u16 {
-        self.sp -= 1;
-        self.mem[self.sp as usize + 1]
+    pub fn pop(&mut self) -> Result<usize, CpuError> {
+        self.addrs.pop().ok_or(CpuError::StackUnderflow)
     }
 }
 
 impl Display for CPU {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
+        writeln!(
             f,
-            "PC: {:#X} | INDEX: {:#X} | DELAY: {:#X} | SOUND: {:#X}\n",
+            "PC: {:#X} | INDEX: {:#X} | DELAY: {:#X} | SOUND: {:#X}",
             self.pc, self.index, self.delay_timer, self.sound_timer
         )?;
         write!(f, "REGISTERS: [ ")?;
         for e in self.registers {
             write!(f, "{:#X} ", e)?;
         }
-        write!(f, "]\n")?;
-        // write!(f, "STACK: {:#?}", self.stack)?;
+        writeln!(f, "]")?;
+        write!(f, "STACK: [ ")?;
+        for addr in &self.stack.addrs {
+            write!(f, "{:#X} ", addr)?;
+        }
+        writeln!(f, "]")?;
         // write!(f, "MEMORY------\n")?;
         // for (i, e) in self.mem.iter().enumerate() {
         //     write!(f, "{:#X} ", e)?;
@@ -125,354 +195,1503 @@ impl Display for CPU {
     }
 }
 
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl CPU {
     pub fn new() -> Self {
         Self::with_size(WIDTH, HEIGHT)
     }
 
+    pub fn with_quirks(quirks: Quirks) -> Self {
+        Self::with_profile(Profile {
+            quirks,
+            ..Profile::default()
+        })
+    }
+
     pub fn with_size(width: usize, height: usize) -> Self {
-        let mut mem = [0u8; 4096];
+        Self::with_profile(Profile {
+            width,
+            height,
+            ..Profile::default()
+        })
+    }
 
-        //load font
-        for i in 0..FONT.len() {
-            mem[i + FONT_START as usize] = FONT[i];
+    pub fn with_platform(platform: Platform) -> Self {
+        Self::with_profile(platform.profile())
+    }
+
+    /// Make a CPU for the profile, panicking if its memory is smaller than `MIN_MEMORY_SIZE`.
+    /// `try_with_profile` returns an error instead.
+    pub fn with_profile(profile: Profile) -> Self {
+        Self::try_with_profile(profile).expect("profile memory should hold at least 0x200 bytes")
+    }
+
+    /// Make a CPU for the profile, failing if its memory is smaller than `MIN_MEMORY_SIZE`
+    pub fn try_with_profile(profile: Profile) -> Result<Self, CpuError> {
+        if profile.memory_size < MIN_MEMORY_SIZE {
+            return Err(CpuError::MemoryTooSmall {
+                size: profile.memory_size,
+            });
         }
 
-        Self {
-            mem,
+        let mut cpu = Self {
+            mem: vec![0u8; profile.memory_size],
+            map: profile.memory_map(),
             pc: 0,
             index: 0,
-            stack: Stack::new(),
+            stack: Stack::new(profile.stack_depth),
             registers: [0u8; 16],
             delay_timer: 0,
             sound_timer: 0,
-            keypad: [false; 16],
-            display: vec![vec![false; width]; height],
+            keypad: [[false; 16]; 2],
+            quirks: profile.quirks,
+            instruction_set: profile.instruction_set,
+            display: Framebuffer::new(profile.width, profile.height),
+            planes: 1,
+            pattern: [0u8; 16],
+            pitch: 64,
+            megachip: (profile.instruction_set == InstructionSet::MegaChip)
+                .then(|| Box::new(MegaChip::new())),
+            mega_mode: false,
+            colours: (profile.instruction_set == InstructionSet::Chip8X).then(ColourZones::new),
+            port: Port::default(),
+            audio: Audio::default(),
+            beeping: false,
+            lores: (profile.width, profile.height),
+            hires: false,
+            two_page: profile.two_page,
+            flags: [0u8; 16],
+            halted: false,
+            frame_done: false,
+            waiting_for_key: false,
+            held_keys: [false; 16],
+            wait_key: None,
+            frame_keypad: [[false; 16]; 2],
+            pressed: [[false; 16]; 2],
+            released: [[false; 16]; 2],
+            pressing: [[false; 16]; 2],
+            releasing: [[false; 16]; 2],
+            rng: RandomSource::seeded(rand::random()),
+            clock: Clock::new(
+                profile
+                    .timing
+                    .cycles_per_frame(profile.instructions_per_frame),
+            ),
+            timing: profile.timing,
+        };
+        // the first frame loses its overhead too, just like the ones after a frame boundary
+        cpu.clock.advance(cpu.timing.frame_overhead());
+        cpu.load_font(&profile.font.font());
+        if profile.quirks.lores_on_hires {
+            cpu.reset_display();
         }
+
+        Ok(cpu)
     }
 
-    /// Load a program into memory starting at address 0x200
-    pub fn load_program(&mut self, program: &[u8]) {
-        for (i, byte) in program.iter().enumerate() {
-            self.mem[i + 0x200] = *byte;
+    /// Replace the font in memory, for FX29 and FX30 to point at
+    pub fn load_font(&mut self, font: &Font) {
+        self.mem[FONT_START..FONT_START + font.small.len()].copy_from_slice(&font.small);
+        let big = &font.big[..font.big.len().min(160)];
+        self.mem[BIG_FONT_START..BIG_FONT_START + 160].fill(0);
+        self.mem[BIG_FONT_START..BIG_FONT_START + big.len()].copy_from_slice(big);
+    }
+
+    /// Load a program into memory at the profile's load address, usually 0x200.
+    ///
+    /// With a two-page hi-res profile, programs that start with a `1260` jump start running at
+    /// 0x2C0 instead.
+    pub fn load_program(&mut self, program: &[u8]) -> Result<(), CpuError> {
+        let start = self.map.program.start;
+        let capacity = self.map.program.len();
+        if program.len() > capacity {
+            return Err(CpuError::RomTooLarge {
+                size: program.len(),
+                capacity,
+            });
+        }
+
+        self.mem[start..start + program.len()].copy_from_slice(program);
+        self.pc = start;
+
+        // the 1260 jump leads into the machine code that patched the interpreter for the
+        // taller display, and the program itself starts after it at 0x2C0
+        if self.two_page && start == 0x200 && program.starts_with(&[0x12, 0x60]) {
+            self.pc = 0x2C0;
         }
-        self.pc = 0x200;
+
+        Ok(())
+    }
+
+    pub fn registers(&self) -> &[u8; 16] {
+        &self.registers
+    }
+
+    /// Set register VX, only the low nibble of `x` is used
+    pub fn set_register(&mut self, x: usize, value: u8) {
+        self.registers[x & 0xF] = value;
     }
 
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn set_index(&mut self, index: u32) {
+        self.index = index;
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+        self.update_beeper();
+    }
+
+    /// Where CXNN gets its random numbers from, which starts out with a random seed
+    pub fn random_source(&self) -> RandomSource {
+        self.rng
+    }
+
+    pub fn set_random_source(&mut self, rng: RandomSource) {
+        self.rng = rng;
+    }
+
+    /// The return addresses of the subroutines that are running, from the outermost call to the innermost
+    pub fn call_stack(&self) -> &[usize] {
+        &self.stack.addrs
+    }
+
+    /// Replace the call stack, failing if it's deeper than the stack can hold
+    pub fn set_call_stack(&mut self, addrs: &[usize]) -> Result<(), CpuError> {
+        if self.stack.depth.is_some_and(|d| addrs.len() > d) {
+            return Err(CpuError::StackOverflow);
+        }
+        self.stack.addrs = addrs.to_vec();
+        Ok(())
+    }
+
+    /// The whole address space
+    pub fn memory(&self) -> &[u8] {
+        &self.mem
+    }
+
+    /// `len` bytes of memory starting at `addr`
+    pub fn read_memory(&self, addr: usize, len: usize) -> Result<&[u8], CpuError> {
+        Ok(&self.mem[self.mem_range(addr, len)?])
+    }
+
+    /// Copy `bytes` into memory starting at `addr`
+    pub fn write_memory(&mut self, addr: usize, bytes: &[u8]) -> Result<(), CpuError> {
+        let range = self.mem_range(addr, bytes.len())?;
+        self.mem[range].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    /// A copy of the program visible state
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            pc: self.pc,
+            index: self.index,
+            registers: self.registers,
+            stack: self.stack.addrs.clone(),
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+            memory: self.mem.clone(),
+            display: self.display.clone(),
+            hires: self.hires,
+            planes: self.planes,
+            pattern: self.pattern,
+            pitch: self.pitch,
+            flags: self.flags,
+            keypad: self.keypad,
+            halted: self.halted,
+            rng: self.rng,
+            clock: self.clock,
+        }
+    }
+
+    /// Put back a state taken with `state`, failing if its stack is deeper than the stack can hold
+    pub fn restore(&mut self, state: &CpuState) -> Result<(), CpuError> {
+        self.set_call_stack(&state.stack)?;
+        self.pc = state.pc;
+        self.index = state.index;
+        self.registers = state.registers;
+        self.delay_timer = state.delay_timer;
+        self.sound_timer = state.sound_timer;
+        self.mem.clone_from(&state.memory);
+        self.display.clone_from(&state.display);
+        self.hires = state.hires;
+        self.planes = state.planes;
+        self.pattern = state.pattern;
+        self.pitch = state.pitch;
+        self.flags = state.flags;
+        self.keypad = state.keypad;
+        self.frame_keypad = state.keypad;
+        self.pressed = [[false; 16]; 2];
+        self.released = [[false; 16]; 2];
+        self.pressing = [[false; 16]; 2];
+        self.releasing = [[false; 16]; 2];
+        // a restored FX0A starts waiting again from scratch
+        self.waiting_for_key = false;
+        self.wait_key = None;
+        self.halted = state.halted;
+        self.rng = state.rng;
+        self.clock = state.clock;
+        if self.has_pattern() {
+            self.send_pattern();
+        }
+        self.update_beeper();
+        Ok(())
+    }
+
+    /// How memory is laid out
+    pub fn memory_map(&self) -> &MemoryMap {
+        &self.map
+    }
+
+    /// Whether MegaChip mode is on, which draws in colour with `draw` rather than to the framebuffer
+    pub fn mega_mode(&self) -> bool {
+        self.mega_mode
+    }
+
+    /// The current size of the display, in pixels
+    pub fn resolution(&self) -> (usize, usize) {
+        self.display.size()
+    }
+
+    /// The display, for frontends that want to draw it themselves
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.display
+    }
+
+    pub fn framebuffer_mut(&mut self) -> &mut Framebuffer {
+        &mut self.display
+    }
+
+    /// The XO-CHIP audio pattern buffer, 128 one-bit samples
+    pub fn audio_pattern(&self) -> &[u8; 16] {
+        &self.pattern
+    }
+
+    /// The XO-CHIP pitch register. The pattern is played at 4000 * 2^((pitch - 64) / 48) samples per second
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    /// The digitised sound MegaChip last started, until 0700 stops it
+    pub fn sample(&self) -> Option<&Sample> {
+        self.megachip.as_ref().and_then(|m| m.sample.as_ref())
+    }
+
+    /// Send the sound to `sink`, starting with what's playing now
+    pub fn set_audio_sink(&mut self, sink: Box<dyn AudioSink>) {
+        self.audio = Audio(sink);
+        let at = self.clock.elapsed();
+        if self.has_pattern() {
+            self.audio.0.pattern(&self.pattern, self.pitch, at);
+        }
+        self.audio.0.beeper(self.beeping, at);
+        if let Some(sample) = self.megachip.as_ref().and_then(|m| m.sample.as_ref()) {
+            self.audio.0.sample(Some(sample), at);
+        }
+    }
+
+    /// Connect something to the CHIP-8X I/O port
+    pub fn set_port_handler(&mut self, handler: Box<dyn PortHandler>) {
+        self.port = Port(Some(handler));
+    }
+
+    /// Whether the program has exited with 00FD
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    /// Emulated time, which decides when the timers tick
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    pub fn clock_mut(&mut self) -> &mut Clock {
+        &mut self.clock
+    }
+
+    /// Tick the 60Hz timers now, rather than waiting for the clock to reach the next frame
     pub fn timers(&mut self) {
         // decrement both counters, leaving them at 0
-        self.sound_timer = self.sound_timer.checked_sub(1).unwrap_or_default();
-        self.delay_timer = self.delay_timer.checked_sub(1).unwrap_or_default();
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.rng.frame();
+        self.latch_keys();
+        self.update_beeper();
+    }
+
+    /// Press or release a key on the first keypad, or the second when `pad` is 1. Unlike writing
+    /// `keypad`, a key that goes down and comes back up within a frame still counts as pressed
+    pub fn set_key(&mut self, pad: usize, key: usize, down: bool) {
+        let (pad, key) = (pad & 1, key & 0xF);
+        if self.keypad[pad][key] != down {
+            let edges = if down {
+                &mut self.pressing
+            } else {
+                &mut self.releasing
+            };
+            edges[pad][key] = true;
+        }
+        self.keypad[pad][key] = down;
+    }
+
+    /// The keys that went down during the last frame, on each keypad
+    pub fn keys_pressed_this_frame(&self) -> [[bool; 16]; 2] {
+        self.pressed
+    }
+
+    /// The keys that came up during the last frame, on each keypad
+    pub fn keys_released_this_frame(&self) -> [[bool; 16]; 2] {
+        self.released
+    }
+
+    // tell the audio sink if the sound timer has just started or stopped the beeper
+    fn update_beeper(&mut self) {
+        let on = self.sound_timer > 0;
+        if on != self.beeping {
+            self.beeping = on;
+            self.audio.0.beeper(on, self.clock.elapsed());
+        }
+    }
+
+    // whether there's an XO-CHIP pattern to play instead of the plain beep, which an all zero buffer
+    // means there isn't yet
+    fn has_pattern(&self) -> bool {
+        self.instruction_set == InstructionSet::XoChip && self.pattern != [0; 16]
+    }
+
+    fn send_pattern(&mut self) {
+        let at = self.clock.elapsed();
+        self.audio.0.pattern(&self.pattern, self.pitch, at);
+    }
+
+    // collect the keys that changed during the frame, either through set_key or by comparing the
+    // keypad with how it was at the last frame boundary
+    fn latch_keys(&mut self) {
+        for pad in 0..2 {
+            for k in 0..16 {
+                let (was, is) = (self.frame_keypad[pad][k], self.keypad[pad][k]);
+                self.pressed[pad][k] = self.pressing[pad][k] || (is && !was);
+                self.released[pad][k] = self.releasing[pad][k] || (was && !is);
+            }
+        }
+        self.frame_keypad = self.keypad;
+        self.pressing = [[false; 16]; 2];
+        self.releasing = [[false; 16]; 2];
+    }
+
+    /// Run a single instruction, and tick the timers if that takes the clock into a new frame.
+    ///
+    /// If the instruction fails the program counter is left pointing at it.
+    pub fn tick(&mut self) -> Result<StepOutcome, CpuError> {
+        if self.halted {
+            return Ok(StepOutcome::Halted);
+        }
+
+        let pc = self.pc;
+        match self.step() {
+            Ok((outcome, cycles)) => {
+                // the rest of the frame is spent waiting for the vertical blank
+                let cycles = if std::mem::take(&mut self.frame_done) {
+                    cycles.max(self.clock.cycles_until_frame())
+                } else {
+                    cycles
+                };
+                self.advance_clock(cycles);
+                Ok(outcome)
+            }
+            Err(e) => {
+                self.pc = pc;
+                self.frame_done = false;
+                Err(e)
+            }
+        }
+    }
+
+    /// Run the rest of the current frame at `instructions_per_frame`, which ends with one 60Hz
+    /// timer tick. From the start of a frame that's exactly `instructions_per_frame` instructions.
+    ///
+    /// With the COSMAC VIP timing model the length of a frame is fixed, and the argument is ignored.
+    pub fn run_frame(&mut self, instructions_per_frame: u32) -> Result<FrameResult, CpuError> {
+        self.clock
+            .set_cycles_per_frame(self.timing.cycles_per_frame(instructions_per_frame));
+        self.run_cycles(self.clock.cycles_until_frame())
+    }
+
+    /// Run for `cycles` of emulated time, ticking the timers at every frame along the way.
+    ///
+    /// Time keeps passing after the program exits. If an instruction fails the clock stops at it.
+    pub fn run_cycles(&mut self, cycles: u64) -> Result<FrameResult, CpuError> {
+        let mut frame = FrameResult::default();
+        let end = self.clock.cycles() + cycles;
+        while self.clock.cycles() < end {
+            if self.halted {
+                self.advance_clock(end - self.clock.cycles());
+                break;
+            }
+
+            frame.instructions += 1;
+            match self.tick()? {
+                StepOutcome::Continue | StepOutcome::Halted => {}
+                StepOutcome::Redraw => frame.display_dirty = true,
+            }
+        }
+
+        frame.sound_active = self.sound_timer > 0;
+        frame.halted = self.halted;
+        frame.waiting_for_key = self.waiting_for_key;
+        Ok(frame)
+    }
+
+    /// Run up to `n` instructions, stopping early if one fails or the program exits
+    pub fn run_instructions(&mut self, n: usize) -> Result<StepOutcome, CpuError> {
+        let mut outcome = StepOutcome::Continue;
+        for _ in 0..n {
+            outcome = self.tick()?;
+            if outcome == StepOutcome::Halted {
+                break;
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    // move the clock on, ticking the timers at the start of each frame
+    fn advance_clock(&mut self, mut cycles: u64) {
+        // stop at each frame boundary, so the timers tick at exactly the right time
+        while cycles >= self.clock.cycles_until_frame() {
+            let rest = self.clock.cycles_until_frame();
+            self.clock.advance(rest);
+            self.timers();
+            cycles = cycles - rest + self.timing.frame_overhead();
+        }
+        self.clock.advance(cycles);
     }
 
-    pub fn tick(&mut self) -> bool {
-        let instr = ((self.mem[self.pc] as u16) << 8) | (self.mem[self.pc + 1] as u16);
+    // run the next instruction, returning how many cycles it took
+    fn step(&mut self) -> Result<(StepOutcome, u64), CpuError> {
+        let pc = self.pc;
+        let instr = u16::from_be_bytes([self.read(pc)?, self.read(pc + 1)?]);
+        let mut decoded = Instruction::decode_for(instr, self.instruction_set)
+            .ok()
+            .filter(|i| self.instruction_set.supports(*i))
+            .ok_or(CpuError::InvalidOpcode { pc, instr })?;
         self.pc += 2;
+        if decoded.has_operand() {
+            let operand = u16::from_be_bytes([self.read(self.pc)?, self.read(self.pc + 1)?]);
+            decoded = decoded.with_operand(operand);
+            self.pc += 2;
+        }
 
-        // all the parts of the current instruction are decoded here to avoid code duplication
-        // though some parts are mutually exclusive, like Y and NN or NNN.
-        // NN is used later for further decoding of instructions like
-        // 0xFX** and 0xEX**
-
-        // first four bits of the instruction
-        let nib = (instr & (0xF << 12)) >> 12;
-        // second four bits of the instruction used to refer to a cpu register
-        let x = ((instr & (0xF << 8)) >> 8) as usize;
-        // third four bits of the instruction used to refer to another cpu register
-        let y = ((instr & (0xF << 4)) >> 4) as usize;
-        // final four bits of the instruction, some 4-bit number
-        let n = instr & (0xF);
-        // lower byte of the instruction, some 8-bit number
-        let nn = (instr & (0xFF)) as u8;
-        // lower twelve bits of the instruction, some 12-bit address
-        let nnn = instr & (0xFFF);
-        // the value of VX
-        let x_val = self.registers[x];
-        // the value of VY
-        let y_val = self.registers[y];
-        // println!("nib: {:#X}", nib);
-        // println!("x: {:#X}", x);
-        // println!("y: {:#X}", y);
-        // println!("n: {:#X}", n);
-        // println!("nn: {:#X}", nn);
-        // println!("nnn: {:#X}", nnn);
-
-        match nib {
-            // clear the display
-            _ if instr == 0x00E0 => {
-                for y in &mut self.display {
-                    y.fill(false);
-                }
+        let cycles = self.timing.cycles(decoded, &self.registers);
+        Ok((self.execute(pc, decoded)?, cycles))
+    }
+
+    /// Run an instruction that has already been fetched from `pc` and decoded
+    fn execute(&mut self, pc: usize, instr: Instruction) -> Result<StepOutcome, CpuError> {
+        use Instruction::*;
+
+        match instr {
+            // in MegaChip mode this shows the frame that's been drawn, and starts a new one
+            Clear if self.mega_mode => {
+                self.mega().present();
+                return Ok(StepOutcome::Redraw);
+            }
+            // clear the selected planes of the display
+            Clear => {
+                self.display.clear(self.planes);
 
-                return true;
+                return Ok(StepOutcome::Redraw);
+            }
+            // the two-page interpreter's clear screen routine
+            Sys(0x230) if self.two_page => {
+                self.display.clear(0b11);
+                return Ok(StepOutcome::Redraw);
             }
             // jump to the address that was at the top of the stack
-            _ if instr == 0x00EE => self.pc = self.stack.pop() as usize,
+            Return => self.pc = self.stack.pop()?,
+            // machine code routines can't be run
+            Sys(_) => {
+                return Err(CpuError::InvalidOpcode {
+                    pc,
+                    instr: instr.encode(),
+                })
+            }
+            // scroll the selected planes of the display, leaving blank pixels behind
+            ScrollDown(n) => {
+                self.scroll(0, n as isize);
+                return Ok(StepOutcome::Redraw);
+            }
+            ScrollUp(n) | ScrollUpMega(n) => {
+                self.scroll(0, -(n as isize));
+                return Ok(StepOutcome::Redraw);
+            }
+            ScrollRight => {
+                self.scroll(4, 0);
+                return Ok(StepOutcome::Redraw);
+            }
+            ScrollLeft => {
+                self.scroll(-4, 0);
+                return Ok(StepOutcome::Redraw);
+            }
+            Exit => {
+                self.halted = true;
+                return Ok(StepOutcome::Halted);
+            }
+            // switching resolution clears the display, unless both share the hires display
+            LowRes | HighRes => {
+                self.hires = instr == HighRes;
+                if !self.quirks.lores_on_hires {
+                    self.reset_display();
+                }
+                return Ok(StepOutcome::Redraw);
+            }
+            MegaOff | MegaOn => {
+                self.mega_mode = instr == MegaOn;
+                self.reset_display();
+                return Ok(StepOutcome::Redraw);
+            }
+            LoadIndex24(addr) => self.index = addr,
+            LoadPalette(nn) => {
+                let range = self.mem_range(self.index as usize, nn as usize * 4)?;
+                let colours = self.mem[range].to_vec();
+                self.mega().load_palette(&colours);
+            }
+            // a size of 0 means 256
+            SpriteWidth(nn) => self.mega().sprite_width = if nn == 0 { 256 } else { nn as usize },
+            SpriteHeight(nn) => self.mega().sprite_height = if nn == 0 { 256 } else { nn as usize },
+            ScreenAlpha(nn) => self.mega().alpha = nn,
+            // the sound starts with a 6 byte header: a 16-bit sample rate, a 24-bit length, and a reserved byte
+            PlaySample(n) => {
+                let header = self.mem_range(self.index as usize, 6)?;
+                let h = &self.mem[header.clone()];
+                let rate = u16::from_be_bytes([h[0], h[1]]);
+                let len = u32::from_be_bytes([0, h[2], h[3], h[4]]) as usize;
+                let data = self.mem_range(header.end, len)?;
+                let sample = Sample {
+                    rate,
+                    data: self.mem[data].to_vec(),
+                    looping: n == 0,
+                };
+                self.audio.0.sample(Some(&sample), self.clock.elapsed());
+                self.mega().sample = Some(sample);
+            }
+            StopSample => {
+                self.audio.0.sample(None, self.clock.elapsed());
+                self.mega().sample = None;
+            }
+            SetBlendMode(n) => {
+                let mode = BlendMode::from_n(n).ok_or(CpuError::InvalidOpcode {
+                    pc,
+                    instr: instr.encode(),
+                })?;
+                self.mega().blend = mode;
+            }
+            CollisionColour(nn) => self.mega().collision = nn,
             // jump to NNN
-            1 => {
+            Jump(nnn) => {
                 self.pc = nnn as usize;
             }
 
-            2 => {
-                self.stack.push(self.pc as u16);
+            Call(nnn) => {
+                self.stack.push(self.pc)?;
                 self.pc = nnn as usize;
             }
 
-            3 => {
-                if x_val == nn {
-                    self.pc += 2;
+            SkipEqByte { x, nn } => {
+                if self.registers[x as usize] == nn {
+                    self.skip();
                 }
             }
 
-            4 => {
-                if x_val != nn {
-                    self.pc += 2;
+            SkipNeByte { x, nn } => {
+                if self.registers[x as usize] != nn {
+                    self.skip();
                 }
             }
 
-            5 => {
-                if x_val == y_val {
-                    self.pc += 2;
+            SkipEqReg { x, y } => {
+                if self.registers[x as usize] == self.registers[y as usize] {
+                    self.skip();
                 }
             }
 
-            6 => {
-                self.registers[x] = nn;
+            // store VX to VY in memory, in reverse order if Y is less than X. The index isn't changed
+            StoreRange { x, y } => {
+                let regs = self.register_range(x, y);
+                let range = self.mem_range(self.index as usize, regs.len())?;
+                for (addr, r) in range.zip(regs) {
+                    self.mem[addr] = self.registers[r];
+                }
             }
 
-            7 => {
-                let (res, _) = self.registers[x].overflowing_add(nn);
-                self.registers[x] = res;
+            // load VX to VY from memory, in reverse order if Y is less than X. The index isn't changed
+            LoadRange { x, y } => {
+                let regs = self.register_range(x, y);
+                let range = self.mem_range(self.index as usize, regs.len())?;
+                for (addr, r) in range.zip(regs) {
+                    self.registers[r] = self.mem[addr];
+                }
+            }
+
+            LoadByte { x, nn } => {
+                self.registers[x as usize] = nn;
+            }
+
+            AddByte { x, nn } => {
+                let x = x as usize;
+                self.registers[x] = self.registers[x].wrapping_add(nn);
             }
 
             // arithmetic and logic
-            8 => match n {
-                // set VX to VY
-                0 => {
-                    self.registers[x] = y_val;
-                }
+            LoadReg { x, y }
+            | Or { x, y }
+            | And { x, y }
+            | Xor { x, y }
+            | AddReg { x, y }
+            | Sub { x, y }
+            | SubN { x, y }
+            | ShiftRight { x, y }
+            | ShiftLeft { x, y } => self.alu(instr, x as usize, y as usize),
 
-                // set VX to VX OR VY
-                1 => {
-                    self.registers[x] = x_val | y_val;
+            // skip if VX is not equal to VY
+            SkipNeReg { x, y } => {
+                if self.registers[x as usize] != self.registers[y as usize] {
+                    self.skip();
                 }
+            }
 
-                // set VX to VX AND VY
-                2 => {
-                    self.registers[x] = x_val & y_val;
-                }
+            // set index register to nnn
+            LoadIndex(nnn) => {
+                self.index = nnn.into();
+            }
 
-                // set VX to VX XOR VY
-                3 => {
-                    self.registers[x] = x_val ^ y_val;
-                }
+            // jump program counter to nnn + V0, or to xnn + VX on interpreters that read this as BXNN
+            JumpOffset(nnn) => {
+                let offset = if self.quirks.jump_with_vx {
+                    self.registers[(nnn >> 8) as usize]
+                } else {
+                    self.registers[0]
+                };
+                self.pc = (nnn + offset as u16) as usize;
+            }
+
+            // set VX to the result of nn AND a random number
+            Random { x, nn } => {
+                self.registers[x as usize] = self.rng.next_byte() & nn;
+            }
 
-                // set VX to VX + VY, and set VF if it overflows
-                4 => {
-                    let (res, carry) = x_val.overflowing_add(y_val);
-                    self.registers[0xF] = carry.into();
-                    self.registers[x] = res;
+            // draw a sprite to the display
+            Draw { x, y, n } => return self.draw_sprite(x as usize, y as usize, n),
+
+            // only the low nibble of VX is used to pick a key
+            // skip if the key at VX is pressed
+            SkipKey { x } => {
+                if self.keypad[0][self.registers[x as usize] as usize & 0xF] {
+                    self.skip();
                 }
+            }
 
-                // set VX to VX - VY, and set VF if it doesn't underflow
-                5 => {
-                    let (res, carry) = x_val.overflowing_sub(y_val);
-                    self.registers[0xF] = (!carry).into();
-                    self.registers[x] = res;
+            // skip if the key at VX is NOT pressed
+            SkipNotKey { x } => {
+                if !self.keypad[0][self.registers[x as usize] as usize & 0xF] {
+                    self.skip();
                 }
+            }
 
-                // set VX to VY - VX, and set VF if it DOESN'T underflow
-                7 => {
-                    let (res, carry) = y_val.overflowing_sub(x_val);
-                    self.registers[0xF] = (!carry).into();
-                    self.registers[x] = res;
+            // the same, but for the second keypad
+            SkipKey2 { x } => {
+                if self.keypad[1][self.registers[x as usize] as usize & 0xF] {
+                    self.skip();
                 }
+            }
 
-                // bitwise shift VX right 1
-                6 => {
-                    //TODO: Optional set VX to VY before shifting
-                    let (res, carry) = x_val.overflowing_shr(1);
-                    self.registers[0xF] = carry.into();
-                    self.registers[x] = res;
+            SkipNotKey2 { x } => {
+                if !self.keypad[1][self.registers[x as usize] as usize & 0xF] {
+                    self.skip();
                 }
+            }
 
-                // bitwise shift VX left 1
-                0xE => {
-                    //TODO: Optional set VX to VY before shifting
-                    let (res, carry) = x_val.overflowing_shl(1);
-                    self.registers[0xF] = carry.into();
-                    self.registers[x] = res;
+            // with nothing connected to the port, output goes nowhere
+            OutputPort { x } => {
+                let val = self.registers[x as usize];
+                if let Some(port) = &mut self.port.0 {
+                    port.output(val);
                 }
+            }
 
-                _ => unimplemented!(),
+            // rerun this instruction until the port has something for us
+            InputPort { x } => match self.port.0.as_mut().and_then(|p| p.input()) {
+                Some(val) => self.registers[x as usize] = val,
+                None => self.pc -= 2,
             },
 
-            // skip if VX is not equal to VY
-            9 => {
-                if x_val != y_val {
-                    self.pc += 2;
-                }
+            CycleBackground => {
+                self.chip8x_colours().cycle_background();
+                return Ok(StepOutcome::Redraw);
             }
 
-            // set index register to nnn
-            0xA => {
-                self.index = nnn;
+            ColourZones { x, y, n } => {
+                let (vx, vy) = (self.registers[x as usize], self.registers[y as usize]);
+                let colour = self.registers[(x as usize + 1) & 0xF];
+                let (left, right) = ((vx & 0xF) as usize, (vx >> 4) as usize);
+                let (top, bottom) = if n == 0 {
+                    ((vy & 0xF) as usize * 4, (vy >> 4) as usize * 4 + 3)
+                } else {
+                    (vy as usize, vy as usize + n as usize - 1)
+                };
+                self.chip8x_colours().fill(left, right, top, bottom, colour);
+                return Ok(StepOutcome::Redraw);
             }
 
-            // jump program counter to nnn + V0
-            0xB => {
-                //TODO: BXNN implementation
-                self.pc = (nnn + self.registers[0] as u16) as usize;
-            }
+            // the address is stored in the two bytes after the instruction
+            LoadLongIndex(nnnn) => self.index = nnnn.into(),
 
-            // set VX to the result of nn AND a random number
-            0xC => {
-                self.registers[x] = rand::random::<u8>() & nn;
+            SelectPlanes(n) => self.planes = n & 0b11,
+
+            LoadAudio => {
+                let range = self.mem_range(self.index as usize, self.pattern.len())?;
+                self.pattern.copy_from_slice(&self.mem[range]);
+                self.send_pattern();
             }
 
-            // draw a sprite to the display
-            0xD => {
-                // X and Y registers are the top left corner coordinates
-                let x_coord = x_val as usize % WIDTH;
-                let mut y_coord = y_val as usize % HEIGHT;
-                self.registers[0xF] = 0;
-                for i in 0..n {
-                    if y_coord >= HEIGHT {
-                        continue;
-                    }
-                    // index register points to where in memory the sprite data starts
-                    // the data will be read for as many lines as the draw command indicates
-                    // in the N nibble
-                    let data = self.mem[self.index as usize + i as usize];
-                    // the left side of the sprite should always start from the same point
-                    let mut x = x_coord;
-                    // need to read bits from left to right
-                    for z in (0..8).rev() {
-                        if x >= WIDTH {
-                            break;
-                        }
-                        // each bit in each line of sprite data represents one pixel
-                        let curr = (data & (1 << (z))) != 0;
-                        // if the pixel in 'on' in the sprite data, it will toggle the state
-                        // of the pixel in the display. If a pixel is turned off this way a
-                        // flag is set. I believe this is how collision detection is achieved
-                        // for most games.
-                        if curr && self.display[y_coord][x] {
-                            self.display[y_coord][x] = false;
-                            self.registers[0xF] = 1;
-                        } else if curr && !self.display[y_coord][x] {
-                            self.display[y_coord][x] = true;
-                        }
-                        x += 1;
-                    }
-                    y_coord += 1;
+            // until a pattern is loaded the pitch has nothing to change, so the plain beep carries on
+            SetPitch { x } => {
+                self.pitch = self.registers[x as usize];
+                if self.has_pattern() {
+                    self.send_pattern();
                 }
-
-                return true;
             }
 
-            0xE => match nn {
-                // skip if the key at VX is pressed
-                0x9E => {
-                    if self.keypad[x_val as usize] {
-                        self.pc += 2;
-                    }
+            // set VX to the value of the delay timer
+            LoadDelay { x } => self.registers[x as usize] = self.delay_timer,
+            // set the delay timer to VX
+            SetDelay { x } => self.delay_timer = self.registers[x as usize],
+            // set the sound timer to VX
+            SetSound { x } => {
+                self.sound_timer = self.registers[x as usize];
+                self.update_beeper();
+            }
+            AddIndex { x } => {
+                // add VX to the index register, optionally setting the overflow flag if the result is greater
+                // than 0x0FFF, which was the original addressable range of the COSMAC version of CHIP-8
+                self.index = self.index.wrapping_add(self.registers[x as usize].into());
+                if self.quirks.index_overflow {
+                    self.registers[0xF] = (self.index > 0x0FFF).into();
+                }
+            }
+            // blocks until a key is pressed, or pressed and released with the key release quirk, then stores
+            // the hex value in VX
+            WaitKey { x } => {
+                let keys = self.keypad[0];
+                // keys that were already held down when the wait started have to be pressed again
+                if !self.waiting_for_key {
+                    self.held_keys = keys;
+                    self.wait_key = None;
+                }
+                if self.wait_key.is_none() {
+                    self.wait_key = (0..16)
+                        .find(|&k| keys[k] && !self.held_keys[k])
+                        .map(|k| k as u8);
                 }
+                self.held_keys = keys;
 
-                // skip if the key at VX is NOT pressed
-                0xA1 => {
-                    if !self.keypad[x_val as usize] {
-                        self.pc += 2;
+                let done = match self.wait_key {
+                    Some(k) if !self.quirks.key_release || !keys[k as usize] => {
+                        self.registers[x as usize] = k;
+                        true
                     }
+                    _ => false,
+                };
+                // we decrement the program counter to rerun this instruction until the key is done with
+                if done {
+                    self.wait_key = None;
+                } else {
+                    self.pc -= 2;
                 }
+                self.waiting_for_key = !done;
+            }
 
-                _ => unimplemented!(),
-            },
+            // set the index register to the location of the sprite data for the font character given by VX,
+            // each glyph is 5 bytes and only the low nibble of VX is used
+            LoadFont { x } => {
+                let c = self.registers[x as usize] & 0xF;
+                self.index = (FONT_START + c as usize * 5) as u32;
+            }
 
-            0xF => match nn {
-                // set VX to the value of the delay timer
-                0x07 => self.registers[x] = self.delay_timer,
-                // set the delay timer to VX
-                0x15 => self.delay_timer = x_val,
-                // set the sound timer to VX
-                0x18 => self.sound_timer = x_val,
-                0x1E => {
-                    // add VX to the index register, setting the overflow flag if the result is greater than 0x0FFF,
-                    // which was the original addressable range of the COSMAC version of CHIP-8
-                    self.index += x_val as u16;
-                    if self.index > 0x0FFF {
-                        self.registers[0xF] = 1;
-                    }
-                }
-                // blocks until a key is pressed, then stores the hex value in VX
-                0x0A => {
-                    let mut pressed = false;
-                    for (i, k) in self.keypad.iter().enumerate() {
-                        if *k {
-                            self.registers[x] = i as u8;
-                            pressed = true;
-                        }
-                    }
-                    // we decrement the program counter to rerun this instruction if no key was pressed
-                    if !pressed {
-                        self.pc -= 2;
-                    }
+            // the same for the big font's 10 byte glyphs, though SCHIP's only has the decimal digits
+            LoadBigFont { x } => {
+                let c = self.registers[x as usize] & 0xF;
+                self.index = (BIG_FONT_START + c as usize * 10) as u32;
+            }
+
+            // binary to decimal conversion on the number in VX, storing the hundreds, tens, and ones places
+            // consecutively in memory starting at the address in the index register
+            StoreBcd { x } => {
+                let val = self.registers[x as usize];
+                let hundreds = val / 100;
+                let tens = (val % 100) / 10;
+                let ones = val % 10;
+                let range = self.mem_range(self.index as usize, 3)?;
+                self.mem[range].copy_from_slice(&[hundreds, tens, ones]);
+            }
+
+            StoreRegs { x } => {
+                // store the registers V0 to VX in memory consecutively, starting at the current index
+                let x = x as usize;
+                let range = self.mem_range(self.index as usize, x + 1)?;
+                self.mem[range].copy_from_slice(&self.registers[..=x]);
+                self.step_index(x);
+            }
+
+            LoadRegs { x } => {
+                // load the registers V0 to VX into memory starting from the current index
+                let x = x as usize;
+                let range = self.mem_range(self.index as usize, x + 1)?;
+                self.registers[..=x].copy_from_slice(&self.mem[range]);
+                self.step_index(x);
+            }
+
+            // save V0 to VX in the user flags
+            StoreFlags { x } => {
+                let x = x as usize;
+                self.flags[..=x].copy_from_slice(&self.registers[..=x]);
+            }
+
+            // restore V0 to VX from the user flags
+            LoadFlags { x } => {
+                let x = x as usize;
+                self.registers[..=x].copy_from_slice(&self.flags[..=x]);
+            }
+        }
+
+        Ok(StepOutcome::Continue)
+    }
+
+    // the 8XYN arithmetic and logic instructions
+    fn alu(&mut self, instr: Instruction, x: usize, y: usize) {
+        use Instruction::*;
+
+        // the value of VX
+        let x_val = self.registers[x];
+        // the value of VY
+        let y_val = self.registers[y];
+
+        match instr {
+            // set VX to VY
+            LoadReg { .. } => {
+                self.registers[x] = y_val;
+            }
+
+            // set VX to VX OR VY
+            Or { .. } => {
+                self.registers[x] = x_val | y_val;
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
                 }
+            }
 
-                // set the index register to the location of the sprite data for the font character given by VX
-                0x29 => {
-                    let c = x_val;
-                    self.index = FONT_START + c as u16;
+            // set VX to VX AND VY
+            And { .. } => {
+                self.registers[x] = x_val & y_val;
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
                 }
+            }
 
-                // binary to decimal conversion on the number in VX, storing the hundreds, tens, and ones places
-                // consecutively in memory starting at the address in the index register
-                0x33 => {
-                    let hundreds = x_val / 100;
-                    let tens = (x_val % 100) / 10;
-                    let ones = x_val % 10;
-                    self.mem[self.index as usize] = hundreds;
-                    self.mem[self.index as usize + 1] = tens;
-                    self.mem[self.index as usize + 2] = ones;
+            // set VX to VX XOR VY
+            Xor { .. } => {
+                self.registers[x] = x_val ^ y_val;
+                if self.quirks.vf_reset {
+                    self.registers[0xF] = 0;
                 }
+            }
 
-                //TODO: original COSMAC behavior - modify the index such that it equals I + X + 1 after the instruction
-                0x55 => {
-                    // store the registers V0 to VX in memory consecutively, starting at the current index
-                    for i in 0..=x {
-                        self.mem[self.index as usize + i] = self.registers[i];
-                    }
+            // the flag is always written after the result, so that it wins when X is F
+
+            // set VX to VX + VY, and set VF if it overflows
+            AddReg { .. } => {
+                let (res, carry) = x_val.overflowing_add(y_val);
+                self.registers[x] = res;
+                self.registers[0xF] = carry.into();
+            }
+
+            // set VX to VX - VY, and set VF if it doesn't underflow
+            Sub { .. } => {
+                let (res, carry) = x_val.overflowing_sub(y_val);
+                self.registers[x] = res;
+                self.registers[0xF] = (!carry).into();
+            }
+
+            // set VX to VY - VX, and set VF if it DOESN'T underflow
+            SubN { .. } => {
+                let (res, carry) = y_val.overflowing_sub(x_val);
+                self.registers[x] = res;
+                self.registers[0xF] = (!carry).into();
+            }
+
+            // bitwise shift VX right 1, setting VF to the bit that was shifted out
+            ShiftRight { .. } => {
+                let val = if self.quirks.shift_uses_vy {
+                    y_val
+                } else {
+                    x_val
+                };
+                self.registers[x] = val >> 1;
+                self.registers[0xF] = val & 1;
+            }
+
+            // bitwise shift VX left 1, setting VF to the bit that was shifted out
+            ShiftLeft { .. } => {
+                let val = if self.quirks.shift_uses_vy {
+                    y_val
+                } else {
+                    x_val
+                };
+                self.registers[x] = val << 1;
+                self.registers[0xF] = val >> 7;
+            }
+
+            _ => unreachable!("{} is not an arithmetic instruction", instr),
+        }
+    }
+
+    // DXYN, draw an N byte sprite at (VX, VY)
+    fn draw_sprite(&mut self, x: usize, y: usize, n: u8) -> Result<StepOutcome, CpuError> {
+        // with the display wait quirk, sprites can only be drawn once per vertical blank,
+        // so this is the last instruction of the frame
+        self.frame_done = self.quirks.display_wait;
+
+        if self.mega_mode {
+            return self.draw_mega_sprite(x, y, n);
+        }
+
+        // with the lores on hires quirk, low resolution pixels are 2x2 on the hires display
+        let scale = if self.lores_on_hires() { 2 } else { 1 };
+        let (width, height) = self.resolution();
+        let (width, height) = (width / scale, height / scale);
+        // SCHIP draws a 16x16 sprite when N is 0, made of two bytes per row
+        let (rows, row_bytes) = if n == 0 && self.instruction_set.has_schip() {
+            (16, 2)
+        } else {
+            (n as usize, 1)
+        };
+
+        // X and Y registers are the top left corner coordinates
+        let x_coord = self.registers[x] as usize % width;
+        let y_coord = self.registers[y] as usize % height;
+        // with XO-CHIP, the sprite data for each selected plane follows on from the last one
+        let sprite_len = rows * row_bytes;
+        let sprite = self.mem_range(
+            self.index as usize,
+            sprite_len * self.planes.count_ones() as usize,
+        )?;
+        let planes = self.planes;
+        let selected = (0..2).filter(|p| planes & (1 << p) != 0);
+        let mut hit_rows = 0;
+        for (plane, sprite_start) in selected.zip(sprite.step_by(sprite_len.max(1))) {
+            let sprite = Sprite {
+                start: sprite_start,
+                rows,
+                row_bytes,
+            };
+            hit_rows += self.draw_plane(plane, sprite, x_coord, y_coord, scale);
+        }
+        self.registers[0xF] = if self.quirks.vf_row_count && self.hires {
+            hit_rows as u8
+        } else {
+            (hit_rows > 0).into()
+        };
+
+        Ok(StepOutcome::Redraw)
+    }
+
+    // in MegaChip mode, sprites have a byte for every pixel that picks a colour from the palette, with 0 being
+    // transparent. Sprites in the interpreter's memory, like the fonts, are still one bit per pixel
+    fn draw_mega_sprite(&mut self, x: usize, y: usize, n: u8) -> Result<StepOutcome, CpuError> {
+        let x_coord = self.registers[x] as usize;
+        let y_coord = self.registers[y] as usize % MEGA_HEIGHT;
+        let one_bit = (self.index as usize) < 0x200;
+        let (width, height) = if one_bit {
+            (8, n as usize)
+        } else {
+            let mega = self.mega();
+            (mega.sprite_width, mega.sprite_height)
+        };
+        let len = if one_bit { height } else { width * height };
+        let range = self.mem_range(self.index as usize, len)?;
+        let sprite = self.mem[range].to_vec();
+
+        let mut collided = false;
+        let mega = self.mega();
+        for row in 0..height {
+            for col in 0..width {
+                let (x, y) = (x_coord + col, y_coord + row);
+                if x >= MEGA_WIDTH || y >= MEGA_HEIGHT {
+                    continue;
                 }
+                collided |= if one_bit {
+                    sprite[row] & (0x80 >> col) != 0 && mega.toggle(x, y)
+                } else {
+                    let colour = sprite[row * width + col];
+                    colour != 0 && mega.plot(x, y, colour)
+                };
+            }
+        }
+        self.registers[0xF] = collided.into();
+
+        Ok(StepOutcome::Redraw)
+    }
 
-                //TODO: original COSMAC behavior - modify the index such that it equals I + X + 1 after the instruction
-                0x65 => {
-                    // load the registers V0 to VX into memory starting from the current index
-                    for i in 0..=x {
-                        self.registers[i] = self.mem[self.index as usize + i];
+    // XOR one plane of a sprite onto the display, with each pixel drawn `scale` times as wide and
+    // tall. Returns how many rows turned a pixel off, along with rows clipped off the bottom
+    fn draw_plane(
+        &mut self,
+        plane: usize,
+        sprite: Sprite,
+        x_coord: usize,
+        y_coord: usize,
+        scale: usize,
+    ) -> usize {
+        let height = self.display.height() / scale;
+        let mut hit_rows = 0;
+        for i in 0..sprite.rows {
+            let mut y = y_coord + i;
+            if y >= height {
+                if self.quirks.clip_sprites {
+                    // SCHIP 1.1 counts the rows clipped off the bottom as collided
+                    if self.quirks.vf_row_count && self.hires {
+                        hit_rows += sprite.rows - i;
                     }
+                    break;
                 }
+                y %= height;
+            }
+            // index register points to where in memory the sprite data starts
+            // the data will be read for as many lines as the draw command indicates
+            // in the N nibble
+            let start = sprite.start + i * sprite.row_bytes;
+            let data = self.mem[start..start + sprite.row_bytes]
+                .iter()
+                .fold(0u64, |row, b| (row << 8) | *b as u64);
+            let (data, bits) = if scale == 2 {
+                (double_bits(data), sprite.row_bytes * 16)
+            } else {
+                (data, sprite.row_bytes * 8)
+            };
+            // each bit in each line of sprite data represents one pixel, which toggles the
+            // pixel under it. If a pixel is turned off this way a flag is set, which is how
+            // most games do collision detection
+            let row = data << (64 - bits);
+            let mut hit = false;
+            for dy in 0..scale {
+                hit |= self.display.xor_row(
+                    plane,
+                    x_coord * scale,
+                    y * scale + dy,
+                    row,
+                    bits,
+                    self.quirks.clip_sprites,
+                );
+            }
+            hit_rows += hit as usize;
+        }
 
-                _ => eprintln!("Unknown instruction: {:#X}", instr),
-            },
+        hit_rows
+    }
+
+    // whether low resolution is being drawn with 2x2 pixels on the high resolution display
+    fn lores_on_hires(&self) -> bool {
+        self.quirks.lores_on_hires && !self.hires && !self.mega_mode
+    }
+
+    // the MegaChip state, which the instruction set check guarantees is there for MegaChip instructions
+    fn mega(&mut self) -> &mut MegaChip {
+        self.megachip
+            .as_mut()
+            .expect("MegaChip instruction run without the MegaChip instruction set")
+    }
 
-            c => eprintln!("opcode: {:#X}", c),
+    // the CHIP-8X colours, which the instruction set check guarantees are there for CHIP-8X instructions
+    fn chip8x_colours(&mut self) -> &mut ColourZones {
+        self.colours
+            .as_mut()
+            .expect("CHIP-8X instruction run without the CHIP-8X instruction set")
+    }
+
+    // blank the display at the size for the current mode
+    fn reset_display(&mut self) {
+        let (width, height) = if self.mega_mode {
+            (MEGA_WIDTH, MEGA_HEIGHT)
+        } else if self.hires || self.quirks.lores_on_hires {
+            (HIRES_WIDTH, HIRES_HEIGHT)
+        } else {
+            self.lores
+        };
+        self.display.resize(width, height);
+    }
+
+    // move the selected planes of the display by (dx, dy) pixels, filling the gap with blank pixels
+    fn scroll(&mut self, dx: isize, dy: isize) {
+        if self.mega_mode {
+            self.mega().scroll(dx, dy);
+            return;
+        }
+
+        self.display.scroll(self.planes, dx, dy);
+    }
+
+    // skip the next instruction, which might be four bytes long, like XO-CHIP's F000 NNNN
+    fn skip(&mut self) {
+        let long = match (self.read(self.pc), self.read(self.pc + 1)) {
+            (Ok(hi), Ok(lo)) => {
+                Instruction::decode_for(u16::from_be_bytes([hi, lo]), self.instruction_set)
+                    .is_ok_and(|i| self.instruction_set.supports(i) && i.has_operand())
+            }
+            _ => false,
+        };
+        self.pc += if long { 4 } else { 2 };
+    }
+
+    // the registers from X to Y, counting down if Y is less than X
+    fn register_range(&self, x: u8, y: u8) -> Vec<usize> {
+        let (x, y) = (x as usize, y as usize);
+        if x <= y {
+            (x..=y).collect()
+        } else {
+            (y..=x).rev().collect()
         }
+    }
+
+    fn read(&self, addr: usize) -> Result<u8, CpuError> {
+        self.mem
+            .get(addr)
+            .copied()
+            .ok_or(CpuError::MemoryOutOfBounds { addr })
+    }
 
-        false
+    // the range of memory covering `len` bytes from `addr`, if all of it exists
+    fn mem_range(&self, addr: usize, len: usize) -> Result<std::ops::Range<usize>, CpuError> {
+        match addr.checked_add(len) {
+            Some(end) if end <= self.mem.len() => Ok(addr..end),
+            _ => Err(CpuError::MemoryOutOfBounds {
+                addr: addr.max(self.mem.len()),
+            }),
+        }
     }
 
+    // move the index past the registers that were just stored or loaded, depending on the load/store quirk
+    fn step_index(&mut self, x: usize) {
+        match self.quirks.load_store {
+            LoadStore::IncrementXPlusOne => self.index = self.index.wrapping_add(x as u32 + 1),
+            LoadStore::IncrementX => self.index = self.index.wrapping_add(x as u32),
+            LoadStore::Unchanged => {}
+        }
+    }
+
+    /// Draw the display into an RGBA frame buffer the same size as the current resolution
     pub fn draw(&self, frame: &mut [u8]) {
-        for (i, pixel) in frame.chunks_exact_mut(4).enumerate() {
-            let x = i % WIDTH;
-            let y = i / WIDTH;
+        if let (true, Some(mega)) = (self.mega_mode, &self.megachip) {
+            mega.render(frame);
+            return;
+        }
 
-            let rgba = if self.display[y][x] {
-                [0xF0, 0x90, 0xF0, 0xFF]
-            } else {
-                [0x50, 0x50, 0x50, 0xFF]
-            };
+        for (pixel, (x, y, px)) in frame.chunks_exact_mut(4).zip(self.display.pixels()) {
+            match &self.colours {
+                Some(colours) => pixel.copy_from_slice(&colours.colour(x, y, px != 0)),
+                None => pixel.copy_from_slice(&PALETTE[px as usize & 0b11]),
+            }
+        }
+    }
+}
+
+// where a sprite is in memory, and its size
+#[derive(Debug, Clone, Copy)]
+struct Sprite {
+    start: usize,
+    rows: usize,
+    row_bytes: usize,
+}
+
+// stretch each of the low 32 bits to two bits, so each pixel covers two on a display twice as wide
+fn double_bits(bits: u64) -> u64 {
+    (0..32).fold(0, |doubled, i| {
+        doubled | (((bits >> i) & 1) * (0b11 << (i * 2)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::CpuBuilder;
+
+    #[test]
+    fn memory_accessors_stay_in_bounds() {
+        let mut cpu = CPU::new();
+        assert_eq!(cpu.read_memory(0xFFE, 2).unwrap().len(), 2);
+        assert_eq!(
+            cpu.read_memory(0xFFF, 2),
+            Err(CpuError::MemoryOutOfBounds { addr: 0x1000 })
+        );
+        assert_eq!(
+            cpu.read_memory(usize::MAX, 2),
+            Err(CpuError::MemoryOutOfBounds { addr: usize::MAX })
+        );
+        assert!(cpu.write_memory(usize::MAX, &[1, 2]).is_err());
+    }
+
+    #[test]
+    fn memory_too_small_for_the_fonts() {
+        let profile = Profile {
+            memory_size: 0x100,
+            ..Profile::default()
+        };
+        assert_eq!(
+            CPU::try_with_profile(profile).err(),
+            Some(CpuError::MemoryTooSmall { size: 0x100 })
+        );
+
+        let profile = Profile {
+            memory_size: MIN_MEMORY_SIZE,
+            load_address: MIN_MEMORY_SIZE,
+            ..Profile::default()
+        };
+        let mut cpu = CPU::try_with_profile(profile).unwrap();
+        cpu.write_memory(0x100, &[0xC0, 0xFF]).unwrap();
+        cpu.set_pc(0x100);
+        cpu.step().unwrap();
+    }
+
+    #[test]
+    fn two_page_programs_need_the_hires_platform() {
+        let program = [0x12, 0x60];
+
+        let mut cpu = CPU::with_platform(Platform::CosmacVip);
+        cpu.load_program(&program).unwrap();
+        assert_eq!(cpu.pc(), 0x200);
+        assert_eq!(cpu.resolution(), (WIDTH, HEIGHT));
+
+        let mut cpu = CPU::with_platform(Platform::CosmacVipHires);
+        cpu.load_program(&program).unwrap();
+        assert_eq!(cpu.pc(), 0x2C0);
+        assert_eq!(cpu.resolution(), (WIDTH, TWO_PAGE_HEIGHT));
+    }
+
+    #[test]
+    fn two_page_clear_screen() {
+        let mut cpu = CPU::with_platform(Platform::CosmacVipHires);
+        cpu.framebuffer_mut().xor_row(0, 0, 63, 0xFF << 56, 8, true);
+        cpu.write_memory(0x200, &[0x02, 0x30]).unwrap();
+        cpu.set_pc(0x200);
+        cpu.step().unwrap();
+        assert!(cpu.framebuffer().pixels().all(|(_, _, px)| px == 0));
+
+        // everywhere else it's a machine code call
+        let mut cpu = CPU::with_platform(Platform::CosmacVip);
+        cpu.write_memory(0x200, &[0x02, 0x30]).unwrap();
+        cpu.set_pc(0x200);
+        assert!(cpu.step().is_err());
+    }
+
+    #[test]
+    fn run_frame_ticks_the_timers_once() {
+        // 1200 - loop forever
+        let mut cpu = CpuBuilder::new()
+            .delay_timer(10)
+            .sound_timer(10)
+            .instructions(&[0x1200])
+            .platform(Platform::CosmacVip)
+            .build()
+            .unwrap();
+        let frame = cpu.run_frame(15).unwrap();
+        assert_eq!(frame.instructions, 15);
+        assert_eq!((cpu.delay_timer(), cpu.sound_timer()), (9, 9));
+
+        // from part way through a frame, only the rest of it is run
+        cpu.run_instructions(5).unwrap();
+        assert_eq!(cpu.delay_timer(), 9);
+        let frame = cpu.run_frame(15).unwrap();
+        assert_eq!(frame.instructions, 10);
+        assert_eq!((cpu.delay_timer(), cpu.sound_timer()), (8, 8));
+        assert_eq!(cpu.clock().frames(), 2);
+    }
+
+    #[test]
+    fn clock_counts_frames_in_cycles() {
+        // with the simple timing model, 15 instructions make a frame on the VIP
+        let mut cpu = CpuBuilder::new()
+            .delay_timer(10)
+            .instructions(&[0x1200])
+            .platform(Platform::CosmacVip)
+            .build()
+            .unwrap();
+        let frame = cpu.run_cycles(40).unwrap();
+        assert_eq!(frame.instructions, 40);
+        assert_eq!((cpu.clock().cycles(), cpu.clock().frames()), (40, 2));
+        assert_eq!(cpu.clock().cycles_until_frame(), 5);
+        assert_eq!(cpu.delay_timer(), 8);
+        cpu.run_cycles(5).unwrap();
+        assert_eq!(cpu.clock().frames(), 3);
+        assert_eq!(cpu.clock().elapsed(), std::time::Duration::from_millis(50));
+
+        // time keeps passing once the program has exited
+        let mut cpu = CpuBuilder::new()
+            .delay_timer(10)
+            .instructions(&[0x00FD])
+            .platform(Platform::SchipModern)
+            .build()
+            .unwrap();
+        let frame = cpu.run_cycles(90).unwrap();
+        assert!(frame.halted);
+        assert_eq!((cpu.clock().frames(), cpu.delay_timer()), (3, 7));
+    }
+
+    #[test]
+    fn display_wait_ends_the_frame() {
+        // D001 - draw, then 1202 - loop forever
+        let draw = CpuBuilder::new()
+            .delay_timer(10)
+            .instructions(&[0xD001, 0x1202]);
+
+        // the VIP waits for the vertical blank after a draw
+        let mut cpu = draw.clone().platform(Platform::CosmacVip).build().unwrap();
+        let frame = cpu.run_frame(15).unwrap();
+        assert_eq!(frame.instructions, 1);
+        assert!(frame.display_dirty);
+        assert_eq!((cpu.clock().frames(), cpu.delay_timer()), (1, 9));
+        assert_eq!(cpu.run_frame(15).unwrap().instructions, 15);
+
+        let mut cpu = draw.platform(Platform::SchipModern).build().unwrap();
+        assert_eq!(cpu.run_frame(30).unwrap().instructions, 30);
+        assert_eq!((cpu.clock().frames(), cpu.delay_timer()), (1, 9));
+    }
+
+    #[test]
+    fn vip_timing_frames() {
+        let profile = Profile {
+            timing: Timing::CosmacVip,
+            ..Platform::CosmacVip.profile()
+        };
+
+        // 1200 takes 23 cycles, so about 113 fit in what's left of every frame, the first included
+        let mut cpu = CpuBuilder::new()
+            .instructions(&[0x1200])
+            .profile(profile)
+            .build()
+            .unwrap();
+        for _ in 0..4 {
+            let frame = cpu.run_frame(15).unwrap();
+            assert!((112..=113).contains(&frame.instructions), "{:?}", frame);
+        }
 
-            pixel.copy_from_slice(&rgba);
+        // a sprite row takes longer when it isn't lined up with a byte of the display
+        let draw = |x| {
+            let cpu = CpuBuilder::new()
+                .register(0, x)
+                .instructions(&[0xD003])
+                .profile(Profile {
+                    quirks: Quirks {
+                        display_wait: false,
+                        ..profile.quirks
+                    },
+                    ..profile
+                })
+                .run(1)
+                .unwrap();
+            cpu.clock().cycles() - cpu.timing.frame_overhead()
+        };
+        assert_eq!(draw(8), 34 + 3 * 22);
+        assert_eq!(draw(9), 34 + 3 * 34);
+    }
+
+    #[test]
+    fn key_edges_within_a_frame() {
+        let mut cpu = CpuBuilder::new()
+            .instructions(&[0x1200])
+            .platform(Platform::CosmacVip)
+            .build()
+            .unwrap();
+
+        // a tap that's over before the frame ends is still a press and a release
+        cpu.run_instructions(3).unwrap();
+        cpu.set_key(0, 5, true);
+        cpu.run_instructions(3).unwrap();
+        cpu.set_key(0, 5, false);
+        cpu.set_key(1, 2, true);
+        cpu.run_frame(15).unwrap();
+        assert!(cpu.keys_pressed_this_frame()[0][5]);
+        assert!(cpu.keys_released_this_frame()[0][5]);
+        assert!(cpu.keys_pressed_this_frame()[1][2]);
+        assert!(!cpu.keys_released_this_frame()[1][2]);
+
+        // holding a key down isn't pressing it again
+        cpu.run_frame(15).unwrap();
+        assert_eq!(cpu.keys_pressed_this_frame(), [[false; 16]; 2]);
+        assert_eq!(cpu.keys_released_this_frame(), [[false; 16]; 2]);
+        cpu.set_key(1, 2, false);
+        cpu.run_frame(15).unwrap();
+        assert!(cpu.keys_released_this_frame()[1][2]);
+    }
+
+    // a sink that records the pitch of every pattern it's sent
+    #[derive(Default, Clone)]
+    struct PatternLog(std::sync::Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl AudioSink for PatternLog {
+        fn beeper(&mut self, _on: bool, _at: std::time::Duration) {}
+
+        fn pattern(&mut self, _pattern: &[u8; 16], pitch: u8, _at: std::time::Duration) {
+            self.0.lock().unwrap().push(pitch);
         }
+
+        fn sample(&mut self, _sample: Option<&Sample>, _at: std::time::Duration) {}
+    }
+
+    #[test]
+    fn pitch_needs_a_pattern() {
+        // F03A - set the pitch to V0, F002 - load the pattern from the index register
+        let log = PatternLog::default();
+        let mut cpu = CpuBuilder::new()
+            .register(0, 100)
+            .memory(0x300, &[0xFF; 16])
+            .index(0x300)
+            .instructions(&[0xF03A, 0xF002, 0xF03A])
+            .platform(Platform::XoChip)
+            .build()
+            .unwrap();
+        cpu.set_audio_sink(Box::new(log.clone()));
+        cpu.run_instructions(1).unwrap();
+        assert_eq!(cpu.pitch(), 100);
+        assert_eq!(*log.0.lock().unwrap(), []);
+        cpu.run_instructions(2).unwrap();
+        assert_eq!(*log.0.lock().unwrap(), [100, 100]);
     }
 }
@@ -8,7 +8,10 @@ use winit::{
 const WIDTH: u32 = 600;
 const HEIGHT: u32 = WIDTH / 2;
 
-pub fn init() -> (Window, EventLoop<()>, Pixels) {
+/// Open the window, with a pixel buffer the size of the CHIP-8 display.
+///
+/// The buffer can be resized with `Pixels::resize_buffer` if the program changes resolution.
+pub fn init(width: usize, height: usize) -> (Window, EventLoop<()>, Pixels) {
     let event_loop = EventLoop::new();
     let window = {
         let size = LogicalSize::new(WIDTH as f64, HEIGHT as f64);
@@ -24,7 +27,7 @@ pub fn init() -> (Window, EventLoop<()>, Pixels) {
     let pixels = {
         let window_size = window.inner_size();
         let st = SurfaceTexture::new(window_size.width, window_size.height, &window);
-        Pixels::new(crate::WIDTH as u32, crate::HEIGHT as u32, st).unwrap()
+        Pixels::new(width as u32, height as u32, st).unwrap()
     };
 
     (window, event_loop, pixels)
@@ -0,0 +1,88 @@
+use std::fmt::Display;
+
+use crate::cpu::MIN_MEMORY_SIZE;
+
+/// Something a program did that the CPU can't carry out.
+///
+/// When `CPU::tick` returns one of these the program counter still points at the
+/// instruction that failed, so the caller can decide whether to halt, skip it, or trap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuError {
+    /// the instruction at `pc` isn't one the CPU knows how to run
+    InvalidOpcode { pc: usize, instr: u16 },
+    /// a subroutine was called with the call stack already full
+    StackOverflow,
+    /// a subroutine returned with nothing on the call stack
+    StackUnderflow,
+    /// memory was read or written past the end of the address space
+    MemoryOutOfBounds { addr: usize },
+    /// the program is bigger than the memory it's being loaded into
+    RomTooLarge { size: usize, capacity: usize },
+    /// the profile's memory is too small to hold the fonts, see `MIN_MEMORY_SIZE`
+    MemoryTooSmall { size: usize },
+}
+
+impl Display for CpuError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CpuError::InvalidOpcode { pc, instr } => {
+                write!(f, "invalid instruction {:#06X} at {:#X}", instr, pc)
+            }
+            CpuError::StackOverflow => write!(f, "call stack overflow"),
+            CpuError::StackUnderflow => write!(f, "return with an empty call stack"),
+            CpuError::MemoryOutOfBounds { addr } => {
+                write!(f, "memory access out of bounds at {:#X}", addr)
+            }
+            CpuError::RomTooLarge { size, capacity } => write!(
+                f,
+                "program is {} bytes but only {} bytes are available",
+                size, capacity
+            ),
+            CpuError::MemoryTooSmall { size } => write!(
+                f,
+                "memory is {} bytes but at least {} are needed",
+                size, MIN_MEMORY_SIZE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for CpuError {}
+
+/// Returned when a two byte value isn't a valid instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeError(pub u16);
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#06X} is not a valid instruction", self.0)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// What happened as a result of running a single instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    /// the instruction ran without touching the display
+    Continue,
+    /// the instruction changed the display, so it should be redrawn
+    Redraw,
+    /// the program has exited, and no more instructions will be run
+    Halted,
+}
+
+/// What happened while running a frame's worth of instructions with `CPU::run_frame`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FrameResult {
+    /// how many instructions were run
+    pub instructions: u32,
+    /// the display changed, so it should be redrawn
+    pub display_dirty: bool,
+    /// the sound timer is running, so the beeper should be on
+    pub sound_active: bool,
+    /// the program has exited, and no more instructions will be run
+    pub halted: bool,
+    /// the program is stopped at FX0A until a key is pressed
+    pub waiting_for_key: bool,
+}
@@ -0,0 +1,366 @@
+/// How many bitplanes a framebuffer has, XO-CHIP uses both and everything else only the first
+pub const PLANES: usize = 2;
+
+/// A display made of packed bitplanes.
+///
+/// Each plane stores a row of pixels as 64-bit words, with the most significant bit of the first
+/// word being the leftmost pixel. A pixel's value is a bitmask of the planes it's set in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    // words per row
+    stride: usize,
+    planes: [Vec<u64>; PLANES],
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Self {
+        let stride = width.div_ceil(64);
+        Self {
+            width,
+            height,
+            stride,
+            planes: std::array::from_fn(|_| vec![0; stride * height]),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The width and height, in pixels
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Change the resolution, which clears every plane
+    pub fn resize(&mut self, width: usize, height: usize) {
+        *self = Self::new(width, height);
+    }
+
+    /// The planes that the pixel at (x, y) is set in
+    pub fn get(&self, x: usize, y: usize) -> u8 {
+        let (word, bit) = self.locate(x, y);
+        (0..PLANES).fold(0, |px, p| {
+            px | ((self.planes[p][word] & bit != 0) as u8) << p
+        })
+    }
+
+    /// Set the pixel at (x, y) in exactly the planes in `value`
+    pub fn set(&mut self, x: usize, y: usize, value: u8) {
+        let (word, bit) = self.locate(x, y);
+        for (p, plane) in self.planes.iter_mut().enumerate() {
+            if value & (1 << p) != 0 {
+                plane[word] |= bit;
+            } else {
+                plane[word] &= !bit;
+            }
+        }
+    }
+
+    /// Turn off every pixel in the selected planes
+    pub fn clear(&mut self, planes: u8) {
+        for plane in self.selected(planes) {
+            plane.fill(0);
+        }
+    }
+
+    /// XOR one row of a sprite onto a single plane, returning true if any pixel was turned off.
+    ///
+    /// `bits` holds `len` pixels, up to 64, starting from its most significant bit. Pixels past the right
+    /// edge are dropped when `clip` is set, otherwise they wrap around to the left edge.
+    pub fn xor_row(
+        &mut self,
+        plane: usize,
+        x: usize,
+        y: usize,
+        bits: u64,
+        len: usize,
+        clip: bool,
+    ) -> bool {
+        let (mut x, mut bits, mut len) = (x % self.width, bits, len);
+        let mut collided = false;
+        // a row can be wider than the display, so wrapping can go round more than once
+        loop {
+            let visible = len.min(self.width - x);
+            let mask = !u64::MAX.checked_shr(visible as u32).unwrap_or(0);
+            collided |= self.xor_span(plane, x, y, bits & mask, visible);
+            if clip || visible == len {
+                return collided;
+            }
+
+            bits = bits.checked_shl(visible as u32).unwrap_or(0);
+            len -= visible;
+            x = 0;
+        }
+    }
+
+    /// Move the selected planes by (dx, dy) pixels, filling the gap with blank pixels
+    pub fn scroll(&mut self, planes: u8, dx: isize, dy: isize) {
+        let (width, height, stride) = (self.width, self.height, self.stride);
+        for plane in self.selected(planes) {
+            // whole rows can be moved at once
+            let shift = dy.unsigned_abs().min(height) * stride;
+            let len = plane.len();
+            if dy > 0 {
+                plane.copy_within(..len - shift, shift);
+                plane[..shift].fill(0);
+            } else if dy < 0 {
+                plane.copy_within(shift.., 0);
+                plane[len - shift..].fill(0);
+            }
+
+            if dx != 0 {
+                for row in plane.chunks_exact_mut(stride) {
+                    shift_row(row, dx);
+                    mask_row(row, width);
+                }
+            }
+        }
+    }
+
+    /// Every pixel, along with its position, from left to right and top to bottom
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize, u8)> + '_ {
+        (0..self.height).flat_map(move |y| (0..self.width).map(move |x| (x, y, self.get(x, y))))
+    }
+
+    // the word holding the pixel at (x, y), and the bit for it in that word
+    fn locate(&self, x: usize, y: usize) -> (usize, u64) {
+        (y * self.stride + x / 64, 1 << (63 - x % 64))
+    }
+
+    fn selected(&mut self, planes: u8) -> impl Iterator<Item = &mut Vec<u64>> {
+        self.planes
+            .iter_mut()
+            .enumerate()
+            .filter(move |(p, _)| planes & (1 << p) != 0)
+            .map(|(_, plane)| plane)
+    }
+
+    // XOR `len` pixels that are known to fit on the row
+    fn xor_span(&mut self, plane: usize, x: usize, y: usize, bits: u64, len: usize) -> bool {
+        if len == 0 || y >= self.height {
+            return false;
+        }
+
+        let (word, _) = self.locate(x, y);
+        let offset = x % 64;
+        let row = &mut self.planes[plane];
+        let mut collided = row[word] & (bits >> offset) != 0;
+        row[word] ^= bits >> offset;
+        if offset + len > 64 {
+            let rest = bits << (64 - offset);
+            collided |= row[word + 1] & rest != 0;
+            row[word + 1] ^= rest;
+        }
+
+        collided
+    }
+}
+
+// shift a row of words right by dx bits, or left if dx is negative
+fn shift_row(row: &mut [u64], dx: isize) {
+    let n = dx.unsigned_abs();
+    let (words, bits) = (n / 64, n % 64);
+    let join = |hi: u64, lo: u64, left: bool| match (bits, left) {
+        (0, _) => hi,
+        (_, true) => (hi << bits) | (lo >> (64 - bits)),
+        (_, false) => (hi >> bits) | (lo << (64 - bits)),
+    };
+
+    if dx > 0 {
+        for i in (0..row.len()).rev() {
+            let src = i.checked_sub(words);
+            let hi = src.map_or(0, |s| row[s]);
+            let lo = src.and_then(|s| s.checked_sub(1)).map_or(0, |s| row[s]);
+            row[i] = join(hi, lo, false);
+        }
+    } else {
+        for i in 0..row.len() {
+            let hi = row.get(i + words).copied().unwrap_or(0);
+            let lo = row.get(i + words + 1).copied().unwrap_or(0);
+            row[i] = join(hi, lo, true);
+        }
+    }
+}
+
+// turn off the bits past the right edge of the display, in the last word of a row
+fn mask_row(row: &mut [u64], width: usize) {
+    if !width.is_multiple_of(64) {
+        if let Some(last) = row.last_mut() {
+            *last &= !(u64::MAX >> (width % 64));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the simplest possible display, one bool per pixel per plane, to check the packed one against
+    struct Reference {
+        width: usize,
+        height: usize,
+        pixels: Vec<[bool; PLANES]>,
+    }
+
+    impl Reference {
+        fn new(width: usize, height: usize) -> Self {
+            Self {
+                width,
+                height,
+                pixels: vec![[false; PLANES]; width * height],
+            }
+        }
+
+        fn xor_row(
+            &mut self,
+            plane: usize,
+            x: usize,
+            y: usize,
+            bits: u64,
+            len: usize,
+            clip: bool,
+        ) -> bool {
+            let mut collided = false;
+            for i in 0..len {
+                let px = x % self.width + i;
+                if bits & (1 << (63 - i)) == 0 || y >= self.height || (clip && px >= self.width) {
+                    continue;
+                }
+                let pixel = &mut self.pixels[y * self.width + px % self.width][plane];
+                collided |= *pixel;
+                *pixel = !*pixel;
+            }
+            collided
+        }
+
+        fn scroll(&mut self, planes: u8, dx: isize, dy: isize) {
+            let old = self.pixels.clone();
+            for y in 0..self.height {
+                for x in 0..self.width {
+                    let from_x = x as isize - dx;
+                    let from_y = y as isize - dy;
+                    let inside = (0..self.width as isize).contains(&from_x)
+                        && (0..self.height as isize).contains(&from_y);
+                    for p in (0..PLANES).filter(|p| planes & (1 << p) != 0) {
+                        self.pixels[y * self.width + x][p] =
+                            inside && old[from_y as usize * self.width + from_x as usize][p];
+                    }
+                }
+            }
+        }
+
+        fn matches(&self, fb: &Framebuffer) -> bool {
+            fb.pixels().all(|(x, y, px)| {
+                let pixel = self.pixels[y * self.width + x];
+                (0..PLANES).all(|p| (px & (1 << p) != 0) == pixel[p])
+            })
+        }
+    }
+
+    // xorshift, so the tests are the same every time
+    fn random(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    const SIZES: [(usize, usize); 6] = [(64, 32), (128, 64), (256, 192), (100, 10), (8, 4), (3, 2)];
+
+    #[test]
+    fn xor_rows_match_the_reference() {
+        let mut state = 0x2545_F491_4F6C_DD1D;
+        for (width, height) in SIZES {
+            let mut fb = Framebuffer::new(width, height);
+            let mut reference = Reference::new(width, height);
+            for _ in 0..2000 {
+                let plane = random(&mut state) as usize % PLANES;
+                let x = random(&mut state) as usize % 300;
+                let y = random(&mut state) as usize % (height + 2);
+                let len = [8, 16, 64][random(&mut state) as usize % 3];
+                let bits = random(&mut state) & !u64::MAX.checked_shr(len as u32).unwrap_or(0);
+                let clip = random(&mut state).is_multiple_of(2);
+                assert_eq!(
+                    fb.xor_row(plane, x, y, bits, len, clip),
+                    reference.xor_row(plane, x, y, bits, len, clip),
+                    "{}x{}: {} pixels at ({}, {}), clip {}",
+                    width,
+                    height,
+                    len,
+                    x,
+                    y,
+                    clip
+                );
+            }
+            assert!(reference.matches(&fb), "{}x{}", width, height);
+        }
+    }
+
+    #[test]
+    fn wrapping_a_row_wider_than_the_display() {
+        let mut fb = Framebuffer::new(8, 1);
+        // the second half lands back on the first, turning it off again
+        assert!(fb.xor_row(0, 0, 0, 0xFFFF << 48, 16, false));
+        assert!(fb.pixels().all(|(_, _, px)| px == 0));
+
+        assert!(!fb.xor_row(0, 4, 0, 0xF00F << 48, 16, false));
+        let lit: Vec<_> = fb.pixels().map(|(_, _, px)| px).collect();
+        assert_eq!(lit, [1, 1, 1, 1, 1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn clipping() {
+        let mut fb = Framebuffer::new(64, 32);
+        assert!(!fb.xor_row(0, 60, 0, 0xFF << 56, 8, true));
+        let lit: Vec<_> = fb
+            .pixels()
+            .filter(|(_, _, px)| *px != 0)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert_eq!(lit, [(60, 0), (61, 0), (62, 0), (63, 0)]);
+        // rows below the display are dropped
+        assert!(!fb.xor_row(0, 0, 32, 0xFF << 56, 8, true));
+    }
+
+    #[test]
+    fn scrolling_matches_the_reference() {
+        let mut state = 0x9E37_79B9_7F4A_7C15;
+        for (width, height) in SIZES {
+            let mut fb = Framebuffer::new(width, height);
+            let mut reference = Reference::new(width, height);
+            for _ in 0..200 {
+                for _ in 0..4 {
+                    let (plane, x, y) = (
+                        random(&mut state) as usize % PLANES,
+                        random(&mut state) as usize,
+                        random(&mut state) as usize % height,
+                    );
+                    let bits = random(&mut state);
+                    fb.xor_row(plane, x, y, bits, 64, false);
+                    reference.xor_row(plane, x, y, bits, 64, false);
+                }
+
+                let planes = (random(&mut state) % 4) as u8;
+                let dx = random(&mut state) as isize % 70;
+                let dy = random(&mut state) as isize % 8;
+                fb.scroll(planes, dx, dy);
+                reference.scroll(planes, dx, dy);
+                assert!(
+                    reference.matches(&fb),
+                    "{}x{}: scroll ({}, {})",
+                    width,
+                    height,
+                    dx,
+                    dy
+                );
+            }
+        }
+    }
+}
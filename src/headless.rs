@@ -0,0 +1,266 @@
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    str::FromStr,
+};
+
+use crate::{clock::FRAMES_PER_SECOND, cpu::CPU, error::CpuError};
+
+/// How many frames a headless run lasts if the program doesn't exit first and no limit is
+/// given, a minute of emulated time
+pub const DEFAULT_HEADLESS_FRAMES: u64 = 60 * FRAMES_PER_SECOND;
+
+/// A key going down or coming up at the start of a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub frame: u64,
+    /// which of the 16 keys on the first keypad
+    pub key: usize,
+    pub pressed: bool,
+}
+
+/// The keys to press and release during a headless run.
+///
+/// Written as a comma separated list of `FRAME+KEY` to press a key, `FRAME-KEY` to release it,
+/// and `FRAME:KEY` to tap it for one frame, with the key in hex, e.g. `60+5,64-5,90:A`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct KeyScript {
+    // in frame order
+    events: Vec<KeyEvent>,
+}
+
+impl KeyScript {
+    pub fn new(mut events: Vec<KeyEvent>) -> Self {
+        // a stable sort, so events on the same frame happen in the order they were given
+        events.sort_by_key(|e| e.frame);
+        Self { events }
+    }
+
+    pub fn events(&self) -> &[KeyEvent] {
+        &self.events
+    }
+
+    /// The events that happen at the start of `frame`
+    pub fn at(&self, frame: u64) -> impl Iterator<Item = &KeyEvent> {
+        self.events.iter().filter(move |e| e.frame == frame)
+    }
+}
+
+impl FromStr for KeyScript {
+    type Err = InvalidKeyScript;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut events = vec![];
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let invalid = || InvalidKeyScript(part.to_string());
+            let split = part.find([':', '+', '-']).ok_or_else(invalid)?;
+            let frame = part[..split].parse::<u64>().map_err(|_| invalid())?;
+            let key = usize::from_str_radix(&part[split + 1..], 16)
+                .ok()
+                .filter(|k| *k < 16)
+                .ok_or_else(invalid)?;
+
+            let event = |frame, pressed| KeyEvent {
+                frame,
+                key,
+                pressed,
+            };
+            match &part[split..split + 1] {
+                "+" => events.push(event(frame, true)),
+                "-" => events.push(event(frame, false)),
+                _ => events.extend([event(frame, true), event(frame + 1, false)]),
+            }
+        }
+
+        Ok(Self::new(events))
+    }
+}
+
+/// Returned when a key script entry can't be read, holding the entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidKeyScript(pub String);
+
+impl Display for InvalidKeyScript {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "invalid key event '{}', expected FRAME+KEY, FRAME-KEY or FRAME:KEY",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidKeyScript {}
+
+/// Run the program without a display until `frames` frames have passed, or until it exits,
+/// pressing keys as `script` says.
+///
+/// `on_frame` is called after every frame with how many frames have been run so far. Returns
+/// how many frames were run.
+pub fn run_headless(
+    cpu: &mut CPU,
+    frames: u64,
+    script: &KeyScript,
+    mut on_frame: impl FnMut(u64, &CPU),
+) -> Result<u64, CpuError> {
+    let mut frame = 0;
+    while frame < frames {
+        for event in script.at(frame) {
+            cpu.set_key(0, event.key, event.pressed);
+        }
+        // the length of a frame comes from the profile the CPU was made with
+        let result = cpu.run_cycles(cpu.clock().cycles_until_frame())?;
+        frame += 1;
+        on_frame(frame, cpu);
+        if result.halted {
+            break;
+        }
+    }
+
+    Ok(frame)
+}
+
+/// What to save the display as
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// in colour, as the display would show it
+    Png,
+    /// black and white, with every lit pixel black
+    Pbm,
+    /// a line of text for each row, with `.` for unlit pixels and `#`, `+` and `@` for lit ones in
+    /// the first, second, and both XO-CHIP planes
+    Ascii,
+}
+
+impl ImageFormat {
+    pub const ALL: [ImageFormat; 3] = [ImageFormat::Png, ImageFormat::Pbm, ImageFormat::Ascii];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Pbm => "pbm",
+            ImageFormat::Ascii => "ascii",
+        }
+    }
+
+    /// The file extension for the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Ascii => "txt",
+            f => f.name(),
+        }
+    }
+
+    /// Write the display as it is now
+    pub fn write(self, cpu: &CPU, out: &mut impl Write) -> io::Result<()> {
+        let (width, height) = cpu.resolution();
+        let mut rgba = vec![0; width * height * 4];
+        cpu.draw(&mut rgba);
+        // the planes each pixel is lit in. MegaChip mode only draws in colour, so there a pixel
+        // is lit in the first plane if it isn't black
+        let pixels: Vec<(usize, usize, u8)> = if cpu.mega_mode() {
+            let lit = rgba.chunks_exact(4).map(|px| (px[..3] != [0, 0, 0]) as u8);
+            (0..height)
+                .flat_map(|y| (0..width).map(move |x| (x, y)))
+                .zip(lit)
+                .map(|((x, y), px)| (x, y, px))
+                .collect()
+        } else {
+            cpu.framebuffer().pixels().collect()
+        };
+        match self {
+            ImageFormat::Png => {
+                let mut encoder = png::Encoder::new(out, width as u32, height as u32);
+                encoder.set_color(png::ColorType::Rgba);
+                encoder.set_depth(png::BitDepth::Eight);
+                encoder
+                    .write_header()
+                    .and_then(|mut w| w.write_image_data(&rgba))
+                    .map_err(io::Error::other)
+            }
+            ImageFormat::Pbm => {
+                write!(out, "P4\n{} {}\n", width, height)?;
+                // each row is padded to a whole number of bytes
+                let mut row = vec![0u8; width.div_ceil(8)];
+                for (x, _, px) in pixels {
+                    if px != 0 {
+                        row[x / 8] |= 0x80 >> (x % 8);
+                    }
+                    if x == width - 1 {
+                        out.write_all(&row)?;
+                        row.fill(0);
+                    }
+                }
+                Ok(())
+            }
+            ImageFormat::Ascii => {
+                for (x, _, px) in pixels {
+                    write!(out, "{}", ['.', '#', '+', '@'][px as usize & 0b11])?;
+                    if x == width - 1 {
+                        writeln!(out)?;
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for ImageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for ImageFormat {
+    type Err = UnknownImageFormat;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ImageFormat::ALL
+            .into_iter()
+            .find(|f| f.name().eq_ignore_ascii_case(s) || f.extension().eq_ignore_ascii_case(s))
+            .ok_or_else(|| UnknownImageFormat(s.to_string()))
+    }
+}
+
+/// Returned when an image format name isn't recognised
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownImageFormat(pub String);
+
+impl Display for UnknownImageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown image format '{}', expected one of:", self.0)?;
+        for format in ImageFormat::ALL {
+            write!(f, " {}", format)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnknownImageFormat {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::CpuBuilder, platform::Platform};
+
+    #[test]
+    fn ascii_in_megachip_mode() {
+        // 0011 - MegaChip mode on, then draw the font's 0 at the top left and show it
+        let mut cpu = CpuBuilder::new()
+            .instructions(&[0x0011, 0xA050, 0xD005, 0x00E0, 0x1208])
+            .platform(Platform::MegaChip)
+            .build()
+            .unwrap();
+        let frames = run_headless(&mut cpu, 2, &KeyScript::default(), |_, _| {}).unwrap();
+        assert_eq!(frames, 2);
+
+        let mut out = vec![];
+        ImageFormat::Ascii.write(&cpu, &mut out).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let rows: Vec<&str> = text.lines().map(|row| &row[..4]).collect();
+        assert_eq!(rows[..6], ["####", "#..#", "#..#", "#..#", "####", "...."]);
+        assert_eq!(text.lines().count(), 192);
+    }
+}
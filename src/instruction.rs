@@ -0,0 +1,671 @@
+use std::fmt::Display;
+
+use crate::error::DecodeError;
+
+/// A single decoded CHIP-8 instruction.
+///
+/// `x` and `y` are register numbers, `nn` is an 8-bit value, `n` a 4-bit value,
+/// and addresses are the lower 12 bits of the instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 00E0 - clear the display
+    Clear,
+    /// 00EE - return from a subroutine
+    Return,
+    /// 0NNN - call a machine code routine on the host computer
+    Sys(u16),
+    /// 00CN - scroll the display down N pixels (SCHIP)
+    ScrollDown(u8),
+    /// 00DN - scroll the display up N pixels (XO-CHIP)
+    ScrollUp(u8),
+    /// 00BN - scroll the display up N pixels (MegaChip)
+    ScrollUpMega(u8),
+    /// 0010 - turn MegaChip mode off (MegaChip)
+    MegaOff,
+    /// 0011 - turn MegaChip mode on (MegaChip)
+    MegaOn,
+    /// 01NN NNNN - set the index register to the 24-bit address made of NN and the next two bytes (MegaChip).
+    /// Decoding only sees the first two bytes, so the low 16 bits are 0 until `with_operand` fills them in
+    LoadIndex24(u32),
+    /// 02NN - load NN ARGB colours from the index register into the palette (MegaChip)
+    LoadPalette(u8),
+    /// 03NN - set the sprite width to NN, or 256 if NN is 0 (MegaChip)
+    SpriteWidth(u8),
+    /// 04NN - set the sprite height to NN, or 256 if NN is 0 (MegaChip)
+    SpriteHeight(u8),
+    /// 05NN - set the screen alpha to NN (MegaChip)
+    ScreenAlpha(u8),
+    /// 060N - play the digitised sound at the index register, looping if N is 0 (MegaChip)
+    PlaySample(u8),
+    /// 0700 - stop the digitised sound (MegaChip)
+    StopSample,
+    /// 080N - set the sprite blend mode to N (MegaChip)
+    SetBlendMode(u8),
+    /// 09NN - set the colour that's checked for sprite collisions to NN (MegaChip)
+    CollisionColour(u8),
+    /// 02A0 - cycle the background colour (CHIP-8X)
+    CycleBackground,
+    /// 00FB - scroll the display right 4 pixels (SCHIP)
+    ScrollRight,
+    /// 00FC - scroll the display left 4 pixels (SCHIP)
+    ScrollLeft,
+    /// 00FD - exit the interpreter (SCHIP)
+    Exit,
+    /// 00FE - switch to the low resolution display (SCHIP)
+    LowRes,
+    /// 00FF - switch to the high resolution display (SCHIP)
+    HighRes,
+    /// 1NNN - jump to NNN
+    Jump(u16),
+    /// 2NNN - call the subroutine at NNN
+    Call(u16),
+    /// 3XNN - skip the next instruction if VX == NN
+    SkipEqByte { x: u8, nn: u8 },
+    /// 4XNN - skip the next instruction if VX != NN
+    SkipNeByte { x: u8, nn: u8 },
+    /// 5XY0 - skip the next instruction if VX == VY
+    SkipEqReg { x: u8, y: u8 },
+    /// 5XY2 - store VX to VY in memory starting at the index register (XO-CHIP)
+    StoreRange { x: u8, y: u8 },
+    /// 5XY3 - load VX to VY from memory starting at the index register (XO-CHIP)
+    LoadRange { x: u8, y: u8 },
+    /// 6XNN - set VX to NN
+    LoadByte { x: u8, nn: u8 },
+    /// 7XNN - add NN to VX, without setting the carry flag
+    AddByte { x: u8, nn: u8 },
+    /// 8XY0 - set VX to VY
+    LoadReg { x: u8, y: u8 },
+    /// 8XY1 - set VX to VX OR VY
+    Or { x: u8, y: u8 },
+    /// 8XY2 - set VX to VX AND VY
+    And { x: u8, y: u8 },
+    /// 8XY3 - set VX to VX XOR VY
+    Xor { x: u8, y: u8 },
+    /// 8XY4 - set VX to VX + VY, VF is the carry
+    AddReg { x: u8, y: u8 },
+    /// 8XY5 - set VX to VX - VY, VF is set when there's no borrow
+    Sub { x: u8, y: u8 },
+    /// 8XY6 - shift VX (or VY) right by one, VF is the bit shifted out
+    ShiftRight { x: u8, y: u8 },
+    /// 8XY7 - set VX to VY - VX, VF is set when there's no borrow
+    SubN { x: u8, y: u8 },
+    /// 8XYE - shift VX (or VY) left by one, VF is the bit shifted out
+    ShiftLeft { x: u8, y: u8 },
+    /// 9XY0 - skip the next instruction if VX != VY
+    SkipNeReg { x: u8, y: u8 },
+    /// ANNN - set the index register to NNN
+    LoadIndex(u16),
+    /// BNNN - jump to NNN + V0, or XNN + VX depending on the jump quirk
+    JumpOffset(u16),
+    /// BXYN - set the foreground colour of a range of zones to V(X+1). VX holds the first and last
+    /// zone column as nibbles. For BXY0 VY holds the first and last 8x4 zone row the same way,
+    /// otherwise the N rows of 8x1 zones starting at VY are coloured (CHIP-8X)
+    ColourZones { x: u8, y: u8, n: u8 },
+    /// CXNN - set VX to a random number AND NN
+    Random { x: u8, nn: u8 },
+    /// DXYN - draw an N byte sprite from the index register at (VX, VY).
+    /// With SCHIP, DXY0 draws a 16x16 sprite
+    Draw { x: u8, y: u8, n: u8 },
+    /// F000 NNNN - set the index register to the 16-bit address in the next two bytes (XO-CHIP).
+    /// Decoding only sees the first two bytes, so the address is 0 until `with_operand` fills it in
+    LoadLongIndex(u16),
+    /// FN01 - select the bitplanes N that drawing, clearing and scrolling affect (XO-CHIP)
+    SelectPlanes(u8),
+    /// F002 - load the 16 byte audio pattern from the index register (XO-CHIP)
+    LoadAudio,
+    /// FX3A - set the audio pattern playback pitch to VX (XO-CHIP)
+    SetPitch { x: u8 },
+    /// EX9E - skip the next instruction if the key in VX is pressed
+    SkipKey { x: u8 },
+    /// EXA1 - skip the next instruction if the key in VX is not pressed
+    SkipNotKey { x: u8 },
+    /// EXF2 - skip the next instruction if the key in VX is pressed on the second keypad (CHIP-8X)
+    SkipKey2 { x: u8 },
+    /// EXF5 - skip the next instruction if the key in VX is not pressed on the second keypad (CHIP-8X)
+    SkipNotKey2 { x: u8 },
+    /// FXF8 - send VX to the I/O port (CHIP-8X)
+    OutputPort { x: u8 },
+    /// FXFB - wait for a value from the I/O port and store it in VX (CHIP-8X)
+    InputPort { x: u8 },
+    /// FX07 - set VX to the delay timer
+    LoadDelay { x: u8 },
+    /// FX0A - wait for a key press and store it in VX
+    WaitKey { x: u8 },
+    /// FX15 - set the delay timer to VX
+    SetDelay { x: u8 },
+    /// FX18 - set the sound timer to VX
+    SetSound { x: u8 },
+    /// FX1E - add VX to the index register
+    AddIndex { x: u8 },
+    /// FX29 - point the index register at the font character in VX
+    LoadFont { x: u8 },
+    /// FX30 - point the index register at the big font character in VX (SCHIP)
+    LoadBigFont { x: u8 },
+    /// FX33 - store the decimal digits of VX at the index register
+    StoreBcd { x: u8 },
+    /// FX55 - store V0 to VX in memory starting at the index register
+    StoreRegs { x: u8 },
+    /// FX65 - load V0 to VX from memory starting at the index register
+    LoadRegs { x: u8 },
+    /// FX75 - store V0 to VX in the RPL user flags (SCHIP)
+    StoreFlags { x: u8 },
+    /// FX85 - load V0 to VX from the RPL user flags (SCHIP)
+    LoadFlags { x: u8 },
+}
+
+/// The families of instructions an interpreter understands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstructionSet {
+    /// the original CHIP-8 instructions
+    Chip8,
+    /// CHIP-8 plus the SUPER-CHIP 1.0 additions, which didn't have scrolling yet
+    Schip10,
+    /// CHIP-8 plus the SUPER-CHIP 1.1 additions
+    Schip,
+    /// SUPER-CHIP plus the XO-CHIP additions
+    XoChip,
+    /// SUPER-CHIP plus the MegaChip additions
+    MegaChip,
+    /// CHIP-8 with the CHIP-8X colour, second keypad and I/O port instructions, which replace BNNN
+    Chip8X,
+}
+
+impl InstructionSet {
+    /// Whether this instruction set includes the SUPER-CHIP instructions
+    pub fn has_schip(self) -> bool {
+        matches!(
+            self,
+            InstructionSet::Schip10
+                | InstructionSet::Schip
+                | InstructionSet::XoChip
+                | InstructionSet::MegaChip
+        )
+    }
+
+    /// Whether an interpreter with this instruction set can run `instr`
+    pub fn supports(self, instr: Instruction) -> bool {
+        use Instruction::*;
+        match instr {
+            ScrollDown(_) | ScrollRight | ScrollLeft => {
+                self.has_schip() && self != InstructionSet::Schip10
+            }
+            Exit | LowRes | HighRes | LoadBigFont { .. } | StoreFlags { .. } | LoadFlags { .. } => {
+                self.has_schip()
+            }
+            ScrollUp(_)
+            | StoreRange { .. }
+            | LoadRange { .. }
+            | LoadLongIndex(_)
+            | SelectPlanes(_)
+            | LoadAudio
+            | SetPitch { .. } => self == InstructionSet::XoChip,
+            ScrollUpMega(_) | MegaOff | MegaOn | LoadIndex24(_) | LoadPalette(_)
+            | SpriteWidth(_) | SpriteHeight(_) | ScreenAlpha(_) | PlaySample(_) | StopSample
+            | SetBlendMode(_) | CollisionColour(_) => self == InstructionSet::MegaChip,
+            CycleBackground
+            | ColourZones { .. }
+            | SkipKey2 { .. }
+            | SkipNotKey2 { .. }
+            | OutputPort { .. }
+            | InputPort { .. } => self == InstructionSet::Chip8X,
+            JumpOffset(_) => self != InstructionSet::Chip8X,
+            _ => true,
+        }
+    }
+}
+
+impl Instruction {
+    /// Decode a two byte instruction for a particular instruction set, which matters for the few
+    /// instructions that mean different things to different interpreters
+    pub fn decode_for(instr: u16, set: InstructionSet) -> Result<Self, DecodeError> {
+        if set == InstructionSet::Chip8X {
+            let x = ((instr >> 8) & 0xF) as u8;
+            let y = ((instr >> 4) & 0xF) as u8;
+            let n = (instr & 0xF) as u8;
+            match instr >> 12 {
+                _ if instr == 0x02A0 => return Ok(Instruction::CycleBackground),
+                0xB => return Ok(Instruction::ColourZones { x, y, n }),
+                _ => {}
+            }
+        }
+
+        if set == InstructionSet::MegaChip && instr >> 12 == 0 {
+            // MegaChip uses most of the 0NNN space, which is left as machine code calls elsewhere
+            let n = (instr & 0xF) as u8;
+            let nn = (instr & 0xFF) as u8;
+            let megachip = match instr {
+                0x00B0..=0x00BF => Some(Instruction::ScrollUpMega(n)),
+                0x0010 => Some(Instruction::MegaOff),
+                0x0011 => Some(Instruction::MegaOn),
+                0x0100..=0x01FF => Some(Instruction::LoadIndex24((nn as u32) << 16)),
+                0x0200..=0x02FF => Some(Instruction::LoadPalette(nn)),
+                0x0300..=0x03FF => Some(Instruction::SpriteWidth(nn)),
+                0x0400..=0x04FF => Some(Instruction::SpriteHeight(nn)),
+                0x0500..=0x05FF => Some(Instruction::ScreenAlpha(nn)),
+                0x0600..=0x060F => Some(Instruction::PlaySample(n)),
+                0x0700 => Some(Instruction::StopSample),
+                0x0800..=0x080F => Some(Instruction::SetBlendMode(n)),
+                0x0900..=0x09FF => Some(Instruction::CollisionColour(nn)),
+                _ => None,
+            };
+            if let Some(i) = megachip {
+                return Ok(i);
+            }
+        }
+
+        match Self::decode(instr)? {
+            // any 0NNN that isn't one of the set's own instructions is a call to machine code
+            i if instr >> 12 == 0 && !set.supports(i) => Ok(Instruction::Sys(instr & 0xFFF)),
+            i => Ok(i),
+        }
+    }
+
+    /// Decode a two byte instruction.
+    ///
+    /// Where interpreters disagree about what an instruction means this picks the most common
+    /// meaning, `decode_for` can be used to decode for a specific instruction set.
+    pub fn decode(instr: u16) -> Result<Self, DecodeError> {
+        // first four bits of the instruction
+        let nib = instr >> 12;
+        // second four bits of the instruction used to refer to a cpu register
+        let x = ((instr >> 8) & 0xF) as u8;
+        // third four bits of the instruction used to refer to another cpu register
+        let y = ((instr >> 4) & 0xF) as u8;
+        // final four bits of the instruction, some 4-bit number
+        let n = (instr & 0xF) as u8;
+        // lower byte of the instruction, some 8-bit number
+        let nn = (instr & 0xFF) as u8;
+        // lower twelve bits of the instruction, some 12-bit address
+        let nnn = instr & 0xFFF;
+
+        use Instruction::*;
+        let decoded = match (nib, n) {
+            (0, _) => match nnn {
+                0x0E0 => Clear,
+                0x0EE => Return,
+                0x0C0..=0x0CF => ScrollDown(n),
+                0x0D0..=0x0DF => ScrollUp(n),
+                0x0FB => ScrollRight,
+                0x0FC => ScrollLeft,
+                0x0FD => Exit,
+                0x0FE => LowRes,
+                0x0FF => HighRes,
+                _ => Sys(nnn),
+            },
+            (1, _) => Jump(nnn),
+            (2, _) => Call(nnn),
+            (3, _) => SkipEqByte { x, nn },
+            (4, _) => SkipNeByte { x, nn },
+            (5, 0) => SkipEqReg { x, y },
+            (5, 2) => StoreRange { x, y },
+            (5, 3) => LoadRange { x, y },
+            (6, _) => LoadByte { x, nn },
+            (7, _) => AddByte { x, nn },
+            (8, 0x0) => LoadReg { x, y },
+            (8, 0x1) => Or { x, y },
+            (8, 0x2) => And { x, y },
+            (8, 0x3) => Xor { x, y },
+            (8, 0x4) => AddReg { x, y },
+            (8, 0x5) => Sub { x, y },
+            (8, 0x6) => ShiftRight { x, y },
+            (8, 0x7) => SubN { x, y },
+            (8, 0xE) => ShiftLeft { x, y },
+            (9, 0) => SkipNeReg { x, y },
+            (0xA, _) => LoadIndex(nnn),
+            (0xB, _) => JumpOffset(nnn),
+            (0xC, _) => Random { x, nn },
+            (0xD, _) => Draw { x, y, n },
+            (0xE, _) => match nn {
+                0x9E => SkipKey { x },
+                0xA1 => SkipNotKey { x },
+                0xF2 => SkipKey2 { x },
+                0xF5 => SkipNotKey2 { x },
+                _ => return Err(DecodeError(instr)),
+            },
+            (0xF, _) => match nn {
+                0x00 if x == 0 => LoadLongIndex(0),
+                0x01 => SelectPlanes(x),
+                0x02 if x == 0 => LoadAudio,
+                0x3A => SetPitch { x },
+                0x07 => LoadDelay { x },
+                0x0A => WaitKey { x },
+                0x15 => SetDelay { x },
+                0x18 => SetSound { x },
+                0x1E => AddIndex { x },
+                0x29 => LoadFont { x },
+                0x30 => LoadBigFont { x },
+                0x33 => StoreBcd { x },
+                0x55 => StoreRegs { x },
+                0x65 => LoadRegs { x },
+                0x75 => StoreFlags { x },
+                0x85 => LoadFlags { x },
+                0xF8 => OutputPort { x },
+                0xFB => InputPort { x },
+                _ => return Err(DecodeError(instr)),
+            },
+            _ => return Err(DecodeError(instr)),
+        };
+
+        Ok(decoded)
+    }
+
+    /// Whether the instruction is followed by two more bytes holding its operand
+    pub fn has_operand(self) -> bool {
+        matches!(
+            self,
+            Instruction::LoadLongIndex(_) | Instruction::LoadIndex24(_)
+        )
+    }
+
+    /// Fill in the operand from the two bytes after an instruction that has one
+    pub fn with_operand(self, operand: u16) -> Self {
+        match self {
+            Instruction::LoadLongIndex(_) => Instruction::LoadLongIndex(operand),
+            Instruction::LoadIndex24(addr) => {
+                Instruction::LoadIndex24(addr & 0xFF_0000 | operand as u32)
+            }
+            i => i,
+        }
+    }
+
+    /// Turn the instruction back into its two byte form, without any operand that follows it
+    pub fn encode(self) -> u16 {
+        // build an instruction out of its four nibbles
+        fn nibbles(a: u16, x: u8, y: u8, n: u8) -> u16 {
+            (a << 12) | ((x as u16 & 0xF) << 8) | ((y as u16 & 0xF) << 4) | (n as u16 & 0xF)
+        }
+        // build an instruction out of a nibble, a register, and a byte
+        fn byte(a: u16, x: u8, nn: u8) -> u16 {
+            (a << 12) | ((x as u16 & 0xF) << 8) | nn as u16
+        }
+        // build an instruction out of a nibble and an address
+        fn addr(a: u16, nnn: u16) -> u16 {
+            (a << 12) | (nnn & 0xFFF)
+        }
+
+        use Instruction::*;
+        match self {
+            Clear => 0x00E0,
+            Return => 0x00EE,
+            Sys(nnn) => addr(0, nnn),
+            ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            ScrollUpMega(n) => 0x00B0 | (n as u16 & 0xF),
+            MegaOff => 0x0010,
+            MegaOn => 0x0011,
+            LoadIndex24(addr) => byte(0, 1, (addr >> 16) as u8),
+            LoadPalette(nn) => byte(0, 2, nn),
+            SpriteWidth(nn) => byte(0, 3, nn),
+            SpriteHeight(nn) => byte(0, 4, nn),
+            ScreenAlpha(nn) => byte(0, 5, nn),
+            PlaySample(n) => 0x0600 | (n as u16 & 0xF),
+            StopSample => 0x0700,
+            SetBlendMode(n) => 0x0800 | (n as u16 & 0xF),
+            CollisionColour(nn) => byte(0, 9, nn),
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            LowRes => 0x00FE,
+            HighRes => 0x00FF,
+            Jump(nnn) => addr(1, nnn),
+            Call(nnn) => addr(2, nnn),
+            SkipEqByte { x, nn } => byte(3, x, nn),
+            SkipNeByte { x, nn } => byte(4, x, nn),
+            SkipEqReg { x, y } => nibbles(5, x, y, 0),
+            StoreRange { x, y } => nibbles(5, x, y, 2),
+            LoadRange { x, y } => nibbles(5, x, y, 3),
+            LoadByte { x, nn } => byte(6, x, nn),
+            AddByte { x, nn } => byte(7, x, nn),
+            LoadReg { x, y } => nibbles(8, x, y, 0x0),
+            Or { x, y } => nibbles(8, x, y, 0x1),
+            And { x, y } => nibbles(8, x, y, 0x2),
+            Xor { x, y } => nibbles(8, x, y, 0x3),
+            AddReg { x, y } => nibbles(8, x, y, 0x4),
+            Sub { x, y } => nibbles(8, x, y, 0x5),
+            ShiftRight { x, y } => nibbles(8, x, y, 0x6),
+            SubN { x, y } => nibbles(8, x, y, 0x7),
+            ShiftLeft { x, y } => nibbles(8, x, y, 0xE),
+            SkipNeReg { x, y } => nibbles(9, x, y, 0),
+            LoadIndex(nnn) => addr(0xA, nnn),
+            JumpOffset(nnn) => addr(0xB, nnn),
+            ColourZones { x, y, n } => nibbles(0xB, x, y, n),
+            CycleBackground => 0x02A0,
+            Random { x, nn } => byte(0xC, x, nn),
+            Draw { x, y, n } => nibbles(0xD, x, y, n),
+            LoadLongIndex(_) => 0xF000,
+            SelectPlanes(n) => byte(0xF, n, 0x01),
+            LoadAudio => 0xF002,
+            SetPitch { x } => byte(0xF, x, 0x3A),
+            SkipKey { x } => byte(0xE, x, 0x9E),
+            SkipNotKey { x } => byte(0xE, x, 0xA1),
+            SkipKey2 { x } => byte(0xE, x, 0xF2),
+            SkipNotKey2 { x } => byte(0xE, x, 0xF5),
+            OutputPort { x } => byte(0xF, x, 0xF8),
+            InputPort { x } => byte(0xF, x, 0xFB),
+            LoadDelay { x } => byte(0xF, x, 0x07),
+            WaitKey { x } => byte(0xF, x, 0x0A),
+            SetDelay { x } => byte(0xF, x, 0x15),
+            SetSound { x } => byte(0xF, x, 0x18),
+            AddIndex { x } => byte(0xF, x, 0x1E),
+            LoadFont { x } => byte(0xF, x, 0x29),
+            LoadBigFont { x } => byte(0xF, x, 0x30),
+            StoreBcd { x } => byte(0xF, x, 0x33),
+            StoreRegs { x } => byte(0xF, x, 0x55),
+            LoadRegs { x } => byte(0xF, x, 0x65),
+            StoreFlags { x } => byte(0xF, x, 0x75),
+            LoadFlags { x } => byte(0xF, x, 0x85),
+        }
+    }
+}
+
+/// Mnemonics follow the conventions of Cowgod's CHIP-8 technical reference
+impl Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use Instruction::*;
+        match *self {
+            Clear => write!(f, "CLS"),
+            Return => write!(f, "RET"),
+            Sys(nnn) => write!(f, "SYS {:#05X}", nnn),
+            ScrollDown(n) => write!(f, "SCD {}", n),
+            ScrollUp(n) => write!(f, "SCU {}", n),
+            ScrollUpMega(n) => write!(f, "SCRU {}", n),
+            MegaOff => write!(f, "MEGAOFF"),
+            MegaOn => write!(f, "MEGAON"),
+            LoadIndex24(addr) => write!(f, "LDHI I, {:#08X}", addr),
+            LoadPalette(nn) => write!(f, "LDPAL {}", nn),
+            SpriteWidth(nn) => write!(f, "SPRW {}", nn),
+            SpriteHeight(nn) => write!(f, "SPRH {}", nn),
+            ScreenAlpha(nn) => write!(f, "ALPHA {:#04X}", nn),
+            PlaySample(n) => write!(f, "DIGISND {}", n),
+            StopSample => write!(f, "STOPSND"),
+            SetBlendMode(n) => write!(f, "BMODE {}", n),
+            CollisionColour(nn) => write!(f, "CCOL {}", nn),
+            ScrollRight => write!(f, "SCR"),
+            ScrollLeft => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            LowRes => write!(f, "LOW"),
+            HighRes => write!(f, "HIGH"),
+            Jump(nnn) => write!(f, "JP {:#05X}", nnn),
+            Call(nnn) => write!(f, "CALL {:#05X}", nnn),
+            SkipEqByte { x, nn } => write!(f, "SE V{:X}, {:#04X}", x, nn),
+            SkipNeByte { x, nn } => write!(f, "SNE V{:X}, {:#04X}", x, nn),
+            SkipEqReg { x, y } => write!(f, "SE V{:X}, V{:X}", x, y),
+            StoreRange { x, y } => write!(f, "LD [I], V{:X}-V{:X}", x, y),
+            LoadRange { x, y } => write!(f, "LD V{:X}-V{:X}, [I]", x, y),
+            LoadByte { x, nn } => write!(f, "LD V{:X}, {:#04X}", x, nn),
+            AddByte { x, nn } => write!(f, "ADD V{:X}, {:#04X}", x, nn),
+            LoadReg { x, y } => write!(f, "LD V{:X}, V{:X}", x, y),
+            Or { x, y } => write!(f, "OR V{:X}, V{:X}", x, y),
+            And { x, y } => write!(f, "AND V{:X}, V{:X}", x, y),
+            Xor { x, y } => write!(f, "XOR V{:X}, V{:X}", x, y),
+            AddReg { x, y } => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Sub { x, y } => write!(f, "SUB V{:X}, V{:X}", x, y),
+            ShiftRight { x, y } => write!(f, "SHR V{:X}, V{:X}", x, y),
+            SubN { x, y } => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            ShiftLeft { x, y } => write!(f, "SHL V{:X}, V{:X}", x, y),
+            SkipNeReg { x, y } => write!(f, "SNE V{:X}, V{:X}", x, y),
+            LoadIndex(nnn) => write!(f, "LD I, {:#05X}", nnn),
+            JumpOffset(nnn) => write!(f, "JP V0, {:#05X}", nnn),
+            ColourZones { x, y, n } => write!(f, "COL V{:X}, V{:X}, {}", x, y, n),
+            CycleBackground => write!(f, "BGCOL"),
+            Random { x, nn } => write!(f, "RND V{:X}, {:#04X}", x, nn),
+            Draw { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            LoadLongIndex(nnnn) => write!(f, "LD I, {:#06X}", nnnn),
+            SelectPlanes(n) => write!(f, "PLANE {}", n),
+            LoadAudio => write!(f, "AUDIO"),
+            SetPitch { x } => write!(f, "PITCH V{:X}", x),
+            SkipKey { x } => write!(f, "SKP V{:X}", x),
+            SkipNotKey { x } => write!(f, "SKNP V{:X}", x),
+            SkipKey2 { x } => write!(f, "SKP2 V{:X}", x),
+            SkipNotKey2 { x } => write!(f, "SKNP2 V{:X}", x),
+            OutputPort { x } => write!(f, "OUT V{:X}", x),
+            InputPort { x } => write!(f, "IN V{:X}", x),
+            LoadDelay { x } => write!(f, "LD V{:X}, DT", x),
+            WaitKey { x } => write!(f, "LD V{:X}, K", x),
+            SetDelay { x } => write!(f, "LD DT, V{:X}", x),
+            SetSound { x } => write!(f, "LD ST, V{:X}", x),
+            AddIndex { x } => write!(f, "ADD I, V{:X}", x),
+            LoadFont { x } => write!(f, "LD F, V{:X}", x),
+            LoadBigFont { x } => write!(f, "LD HF, V{:X}", x),
+            StoreBcd { x } => write!(f, "LD B, V{:X}", x),
+            StoreRegs { x } => write!(f, "LD [I], V{:X}", x),
+            LoadRegs { x } => write!(f, "LD V{:X}, [I]", x),
+            StoreFlags { x } => write!(f, "LD R, V{:X}", x),
+            LoadFlags { x } => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETS: [InstructionSet; 6] = [
+        InstructionSet::Chip8,
+        InstructionSet::Schip10,
+        InstructionSet::Schip,
+        InstructionSet::XoChip,
+        InstructionSet::MegaChip,
+        InstructionSet::Chip8X,
+    ];
+
+    #[test]
+    fn decode_encode_round_trip() {
+        for set in SETS {
+            for instr in 0..=u16::MAX {
+                if let Ok(decoded) = Instruction::decode_for(instr, set) {
+                    assert_eq!(
+                        decoded.encode(),
+                        instr,
+                        "{:#06X} as {:?} on {:?}",
+                        instr,
+                        decoded,
+                        set
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn decode_for_set_differences() {
+        use Instruction::*;
+        assert_eq!(
+            Instruction::decode_for(0xB123, InstructionSet::Chip8),
+            Ok(JumpOffset(0x123))
+        );
+        assert_eq!(
+            Instruction::decode_for(0xB123, InstructionSet::Chip8X),
+            Ok(ColourZones { x: 1, y: 2, n: 3 })
+        );
+        assert_eq!(
+            Instruction::decode_for(0x02A0, InstructionSet::Chip8X),
+            Ok(CycleBackground)
+        );
+        // 0NNN instructions the set doesn't have are machine code calls
+        assert_eq!(
+            Instruction::decode_for(0x00C1, InstructionSet::Chip8),
+            Ok(Sys(0x0C1))
+        );
+        assert_eq!(
+            Instruction::decode_for(0x00C1, InstructionSet::Schip),
+            Ok(ScrollDown(1))
+        );
+        assert_eq!(
+            Instruction::decode_for(0x0230, InstructionSet::Chip8),
+            Ok(Sys(0x230))
+        );
+        assert_eq!(
+            Instruction::decode_for(0x0230, InstructionSet::MegaChip),
+            Ok(LoadPalette(0x30))
+        );
+        assert_eq!(
+            Instruction::decode_for(0x0123, InstructionSet::MegaChip),
+            Ok(LoadIndex24(0x23_0000))
+        );
+    }
+
+    #[test]
+    fn invalid_instructions() {
+        for instr in [0x5121, 0x8128, 0xE1FF, 0xF1FF, 0xF100] {
+            assert_eq!(Instruction::decode(instr), Err(DecodeError(instr)));
+        }
+    }
+
+    #[test]
+    fn operands() {
+        let long = Instruction::decode(0xF000).unwrap();
+        assert!(long.has_operand());
+        assert_eq!(
+            long.with_operand(0x1234),
+            Instruction::LoadLongIndex(0x1234)
+        );
+        let long = Instruction::decode_for(0x0112, InstructionSet::MegaChip).unwrap();
+        assert!(long.has_operand());
+        assert_eq!(
+            long.with_operand(0x3456),
+            Instruction::LoadIndex24(0x12_3456)
+        );
+        assert_eq!(long.with_operand(0x3456).encode(), 0x0112);
+        assert!(!Instruction::Clear.has_operand());
+        assert_eq!(Instruction::Clear.with_operand(0x1234), Instruction::Clear);
+    }
+
+    #[test]
+    fn mnemonics() {
+        let cases = [
+            (0x00E0, "CLS"),
+            (0x00EE, "RET"),
+            (0x0123, "SYS 0x123"),
+            (0x0200, "SYS 0x200"),
+            (0x1ABC, "JP 0xABC"),
+            (0x2ABC, "CALL 0xABC"),
+            (0x3A12, "SE VA, 0x12"),
+            (0x5AB0, "SE VA, VB"),
+            (0x5AB2, "LD [I], VA-VB"),
+            (0x6A12, "LD VA, 0x12"),
+            (0x8AB6, "SHR VA, VB"),
+            (0x8ABE, "SHL VA, VB"),
+            (0xAABC, "LD I, 0xABC"),
+            (0xBABC, "JP V0, 0xABC"),
+            (0xCA12, "RND VA, 0x12"),
+            (0xDAB5, "DRW VA, VB, 5"),
+            (0xEA9E, "SKP VA"),
+            (0xFA0A, "LD VA, K"),
+            (0xFA33, "LD B, VA"),
+            (0xFA55, "LD [I], VA"),
+            (0xFA65, "LD VA, [I]"),
+        ];
+        for (instr, text) in cases {
+            assert_eq!(Instruction::decode(instr).unwrap().to_string(), text);
+        }
+        assert_eq!(
+            Instruction::LoadLongIndex(0x1234).to_string(),
+            "LD I, 0x1234"
+        );
+        assert_eq!(
+            Instruction::LoadIndex24(0x01_2345).to_string(),
+            "LDHI I, 0x012345"
+        );
+    }
+}
@@ -1,14 +1,68 @@
+mod audio;
+mod builder;
+mod chip8x;
+mod clock;
+mod conformance;
 mod cpu;
 pub mod display;
+mod error;
+mod font;
+mod framebuffer;
+mod headless;
+mod instruction;
+mod megachip;
+mod memory;
+mod platform;
+mod quirks;
+mod random;
+mod state;
+mod timing;
+mod wav;
 
+#[cfg(feature = "audio")]
+pub use audio::AudioOutput;
+pub use audio::{
+    pattern_rate, AudioError, AudioSink, NullSink, Synth, UnknownWaveform, Waveform,
+    BEEP_FREQUENCY, DEFAULT_SAMPLE_RATE, MAX_SAMPLE_RATE,
+};
+pub use builder::CpuBuilder;
+pub use chip8x::PortHandler;
+pub use clock::{Clock, FRAMES_PER_SECOND};
+pub use conformance::{
+    framebuffer_hash, run_case, run_suite, Case, Goldens, InvalidGolden, Outcome, Report, CASES,
+};
 pub use cpu::CPU;
 pub use cpu::DEFAULT_KEYPAD;
 pub use cpu::HEIGHT;
+pub use cpu::MIN_MEMORY_SIZE;
+pub use cpu::SECOND_KEYPAD;
+pub use cpu::TWO_PAGE_HEIGHT;
 pub use cpu::WIDTH;
+pub use cpu::{HIRES_HEIGHT, HIRES_WIDTH};
+pub use error::{CpuError, DecodeError, FrameResult, StepOutcome};
+pub use font::{Font, FontSet, InvalidFont, UnknownFont};
+pub use framebuffer::{Framebuffer, PLANES};
+pub use headless::{
+    run_headless, ImageFormat, InvalidKeyScript, KeyEvent, KeyScript, UnknownImageFormat,
+    DEFAULT_HEADLESS_FRAMES,
+};
+pub use instruction::{Instruction, InstructionSet};
+pub use megachip::{BlendMode, Sample, MEGA_HEIGHT, MEGA_WIDTH};
+pub use memory::{MemoryMap, Region};
+pub use platform::{Platform, Profile, UnknownPlatform};
+pub use quirks::{LoadStore, Quirks};
+pub use random::RandomSource;
+pub use state::CpuState;
+pub use timing::{Timing, VIP_CYCLES_PER_FRAME};
+pub use wav::WavSink;
 
-pub fn init(program: &[u8]) -> cpu::CPU {
-    let mut c = cpu::CPU::new();
-    c.load_program(program);
+pub fn init(program: &[u8]) -> Result<cpu::CPU, CpuError> {
+    init_with(program, Profile::default())
+}
+
+pub fn init_with(program: &[u8], profile: Profile) -> Result<cpu::CPU, CpuError> {
+    let mut c = cpu::CPU::try_with_profile(profile)?;
+    c.load_program(program)?;
 
-    c
+    Ok(c)
 }
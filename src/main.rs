@@ -1,12 +1,22 @@
 use std::{
     env::args,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
     process::exit,
-    sync::{Arc, Mutex},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     thread,
     time::{Duration, Instant},
 };
 
-use potato::{self, DEFAULT_KEYPAD};
+use potato::{
+    self, AudioSink, Font, FontSet, Goldens, ImageFormat, KeyScript, Outcome, Platform, Profile,
+    RandomSource, Timing, WavSink, Waveform, CPU, DEFAULT_HEADLESS_FRAMES, DEFAULT_KEYPAD,
+    DEFAULT_SAMPLE_RATE, MAX_SAMPLE_RATE, SECOND_KEYPAD,
+};
 use winit::{
     event::{ElementState, Event, VirtualKeyCode, WindowEvent},
     event_loop::ControlFlow,
@@ -14,54 +24,504 @@ use winit::{
 
 // const PROGRAM: &'static [u8; 132] = include_bytes!("IBM_Logo.ch8");
 
+const USAGE: &str = "Usage: potato [OPTIONS] <FILE>
+       potato headless [OPTIONS] [--frames <N>] [--keys <SCRIPT>] [--format <png|pbm|ascii>] [--output <FILE>] [--dump-frames <DIR>] <FILE>
+       potato conformance [--golden <FILE>] [--bless | --allow-missing] [--dump-screens <DIR>] [ROM_DIR]
+Options: [--platform <NAME>] [--font <NAME|FILE>] [--stack-depth <N|unlimited>] [--seed <N> | --vip-random] [--speed <FACTOR>] [--vip-timing] [--waveform <NAME> | --mute] [--record-audio <FILE> [--sample-rate <HZ>]]";
+
+// where the conformance subcommand looks for the Timendus test suite, and the hashes of its screens
+const CONFORMANCE_ROMS: &str = "roms/timendus";
+const CONFORMANCE_GOLDEN: &str = "roms/timendus.golden";
+
+// how much faster than normal the program runs while fast forward is held down
+const FAST_FORWARD: f64 = 8.0;
+// the most emulated time that will be caught up on at once, after a stall
+const MAX_CATCH_UP: Duration = Duration::from_millis(250);
+
+// settings from the command line that are applied to the CPU once it's been made
+#[derive(Default)]
+struct Options {
+    font: Option<Font>,
+    rng: Option<RandomSource>,
+    speed: Option<f64>,
+    waveform: Option<Waveform>,
+    mute: bool,
+    record_audio: Option<String>,
+    // samples per second in the recording
+    sample_rate: Option<u32>,
+}
+
+// settings for running without a window
+#[derive(Default)]
+struct Headless {
+    frames: Option<u64>,
+    keys: KeyScript,
+    format: Option<ImageFormat>,
+    // where the last frame goes, or stdout if it isn't given
+    output: Option<String>,
+    // a directory to save every frame to
+    dump_frames: Option<String>,
+}
+
+// a WAV file the sound is being recorded to
+type Recording = Arc<Mutex<WavSink<BufWriter<File>>>>;
+
 fn main() {
-    let args: Vec<String> = args().collect();
-    // println!("args: {:?}", args);
+    let mut args = args().skip(1).peekable();
+    if args.next_if(|a| a == "conformance").is_some() {
+        conformance(args);
+    }
+    let headless_mode = args.next_if(|a| a == "headless").is_some();
+    let mut headless = Headless::default();
+    // the first headless-only flag given, to complain about if it's given without the subcommand
+    let mut headless_flag = None;
+    let mut profile = Profile::default();
+    let mut options = Options::default();
+    let mut stack_depth = None;
+    let mut vip_timing = false;
+    let mut path = None;
 
-    if args.len() != 2 {
-        eprintln!("Usage: potato <FILE>");
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--platform" | "-p" => {
+                let platform = args
+                    .next()
+                    .unwrap_or_else(|| usage_error("--platform needs a value"));
+                match platform.parse::<Platform>() {
+                    Ok(p) => profile = p.profile(),
+                    Err(e) => usage_error(&e.to_string()),
+                }
+            }
+            // either one of the built in fonts, or a file to load one from
+            "--font" | "-f" => {
+                let name = args
+                    .next()
+                    .unwrap_or_else(|| usage_error("--font needs a value"));
+                options.font = Some(match name.parse::<FontSet>() {
+                    Ok(f) => f.font(),
+                    Err(e) => {
+                        let bytes =
+                            std::fs::read(&name).unwrap_or_else(|_| usage_error(&e.to_string()));
+                        Font::from_bytes(&bytes).unwrap_or_else(|e| usage_error(&e.to_string()))
+                    }
+                });
+            }
+            "--stack-depth" => {
+                let depth = args
+                    .next()
+                    .unwrap_or_else(|| usage_error("--stack-depth needs a value"));
+                stack_depth =
+                    Some(match depth.as_str() {
+                        "unlimited" => None,
+                        n => Some(n.parse::<usize>().unwrap_or_else(|_| {
+                            usage_error(&format!("invalid stack depth '{}'", n))
+                        })),
+                    });
+            }
+            "--seed" => {
+                let seed = args
+                    .next()
+                    .unwrap_or_else(|| usage_error("--seed needs a value"));
+                let seed = seed
+                    .parse::<u64>()
+                    .unwrap_or_else(|_| usage_error(&format!("invalid seed '{}'", seed)));
+                options.rng = Some(RandomSource::seeded(seed));
+            }
+            "--speed" => {
+                let speed = args
+                    .next()
+                    .unwrap_or_else(|| usage_error("--speed needs a value"));
+                options.speed = Some(
+                    speed
+                        .parse::<f64>()
+                        .ok()
+                        .filter(|s| *s > 0.0)
+                        .unwrap_or_else(|| usage_error(&format!("invalid speed '{}'", speed))),
+                );
+            }
+            "--waveform" => {
+                let waveform = args
+                    .next()
+                    .unwrap_or_else(|| usage_error("--waveform needs a value"));
+                options.waveform = Some(
+                    waveform
+                        .parse::<Waveform>()
+                        .unwrap_or_else(|e| usage_error(&e.to_string())),
+                );
+            }
+            "--mute" => options.mute = true,
+            "--record-audio" => {
+                options.record_audio = Some(
+                    args.next()
+                        .unwrap_or_else(|| usage_error("--record-audio needs a value")),
+                );
+            }
+            "--sample-rate" => {
+                let rate = args
+                    .next()
+                    .unwrap_or_else(|| usage_error("--sample-rate needs a value"));
+                options.sample_rate = Some(
+                    rate.parse::<u32>()
+                        .ok()
+                        .filter(|r| (1..=MAX_SAMPLE_RATE).contains(r))
+                        .unwrap_or_else(|| {
+                            usage_error(&format!(
+                                "invalid sample rate '{}', it should be from 1 to {}",
+                                rate, MAX_SAMPLE_RATE
+                            ))
+                        }),
+                );
+            }
+            "--vip-timing" => vip_timing = true,
+            "--vip-random" => options.rng = Some(RandomSource::cosmac_vip()),
+            "--frames" | "--keys" | "--format" | "--output" | "--dump-frames" => {
+                let value = args
+                    .next()
+                    .unwrap_or_else(|| usage_error(&format!("{} needs a value", arg)));
+                match arg.as_str() {
+                    "--frames" => {
+                        headless.frames = Some(value.parse::<u64>().unwrap_or_else(|_| {
+                            usage_error(&format!("invalid frame count '{}'", value))
+                        }))
+                    }
+                    "--keys" => {
+                        headless.keys = value
+                            .parse::<KeyScript>()
+                            .unwrap_or_else(|e| usage_error(&e.to_string()))
+                    }
+                    "--format" => {
+                        headless.format = Some(
+                            value
+                                .parse::<ImageFormat>()
+                                .unwrap_or_else(|e| usage_error(&e.to_string())),
+                        )
+                    }
+                    "--output" => headless.output = Some(value),
+                    _ => headless.dump_frames = Some(value),
+                }
+                headless_flag.get_or_insert(arg);
+            }
+            _ if path.is_none() => path = Some(arg),
+            _ => usage_error(&format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    // applied after the loop so they aren't undone by a later --platform
+    if let Some(depth) = stack_depth {
+        profile.stack_depth = depth;
+    }
+    if vip_timing {
+        profile.timing = Timing::CosmacVip;
+    }
+
+    if let (false, Some(flag)) = (headless_mode, headless_flag) {
+        usage_error(&format!("{} only works with the headless subcommand", flag));
+    }
+    if options.sample_rate.is_some() && options.record_audio.is_none() {
+        usage_error("--sample-rate only works with --record-audio");
+    }
+
+    let Some(path) = path else {
+        usage_error("no ROM file given");
+    };
+    match std::fs::read(&path) {
+        Ok(prog) if headless_mode => run_headless(&prog, profile, options, headless),
+        Ok(prog) => run(&prog, profile, options),
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", path, e);
+            exit(1);
+        }
+    }
+}
+
+// run the Timendus test suite and compare the screens it ends on with the golden hashes
+fn conformance(mut args: impl Iterator<Item = String>) -> ! {
+    let mut golden = CONFORMANCE_GOLDEN.to_string();
+    let mut bless = false;
+    let mut allow_missing = false;
+    let mut dump_screens = None;
+    let mut rom_dir = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--golden" => {
+                golden = args
+                    .next()
+                    .unwrap_or_else(|| usage_error("--golden needs a value"))
+            }
+            "--bless" => bless = true,
+            "--allow-missing" => allow_missing = true,
+            "--dump-screens" => {
+                dump_screens = Some(
+                    args.next()
+                        .unwrap_or_else(|| usage_error("--dump-screens needs a value")),
+                )
+            }
+            _ if rom_dir.is_none() => rom_dir = Some(arg),
+            _ => usage_error(&format!("unexpected argument '{}'", arg)),
+        }
+    }
+    let rom_dir = rom_dir.unwrap_or_else(|| CONFORMANCE_ROMS.to_string());
+
+    // a missing golden file is the same as an empty one, so the first --bless can make it
+    let mut goldens = match std::fs::read_to_string(&golden) {
+        Ok(text) => text.parse::<Goldens>().unwrap_or_else(|e| {
+            eprintln!("Unable to read {}: {}", golden, e);
+            exit(1);
+        }),
+        Err(_) => Goldens::default(),
+    };
+    if let Some(dir) = &dump_screens {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!("Unable to create {}: {}", dir, e);
+            exit(1);
+        }
+    }
+
+    let reports = potato::run_suite(Path::new(&rom_dir), &goldens, |report, cpu| {
+        println!("{}", report);
+        if let Some(dir) = &dump_screens {
+            let name = format!("{}-{}.png", report.case, report.platform);
+            save_image(cpu, ImageFormat::Png, &Path::new(dir).join(name));
+        }
+    });
+    let count = |f: fn(&Outcome) -> bool| reports.iter().filter(|r| f(&r.outcome)).count();
+    let failed = count(|o| matches!(o, Outcome::Fail { .. } | Outcome::Error(_)));
+    let skipped = count(|o| matches!(o, Outcome::Skipped { .. }));
+    let unknown = count(|o| *o == Outcome::Unknown);
+    println!(
+        "{} passed, {} failed, {} skipped, {} without a golden hash",
+        count(|o| *o == Outcome::Pass),
+        failed,
+        skipped,
+        unknown,
+    );
+    if reports
+        .iter()
+        .all(|r| matches!(r.outcome, Outcome::Skipped { .. }))
+    {
+        println!("No test ROMs found in {}", rom_dir);
+    }
+
+    if bless {
+        for report in &reports {
+            if let Some(hash) = report.hash {
+                goldens.set(report.case, report.platform, hash);
+            }
+        }
+        let text = format!(
+            "# the hash of the screen each conformance case ends on, written by potato conformance --bless\n{}",
+            goldens
+        );
+        if let Err(e) = std::fs::write(&golden, text) {
+            eprintln!("Unable to write {}: {}", golden, e);
+            exit(1);
+        }
+        exit(0);
+    }
+
+    // a case that wasn't checked hasn't passed, so it only counts as one when asked to
+    if skipped + unknown > 0 && !allow_missing {
+        eprintln!(
+            "{} cases weren't checked, fetch the ROMs with `make timendus` and bless their screens, or pass --allow-missing",
+            skipped + unknown
+        );
+        exit(1);
+    }
+    exit(if failed > 0 { 1 } else { 0 });
+}
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("{}\n{}", msg, USAGE);
+    exit(1);
+}
+
+// play the sound through the default output device
+#[cfg(feature = "audio")]
+fn start_audio(
+    options: &Options,
+    sinks: &mut Vec<Box<dyn AudioSink>>,
+) -> Option<potato::AudioOutput> {
+    if options.mute {
+        return None;
+    }
+    match potato::AudioOutput::new(options.waveform.unwrap_or_default()) {
+        Ok(output) => {
+            sinks.push(Box::new(output.synth()));
+            Some(output)
+        }
+        Err(e) => {
+            eprintln!("{}, carrying on without sound", e);
+            None
+        }
+    }
+}
+
+#[cfg(not(feature = "audio"))]
+fn start_audio(options: &Options, _sinks: &mut Vec<Box<dyn AudioSink>>) -> Option<()> {
+    if options.waveform.is_some() && options.record_audio.is_none() && !options.mute {
+        eprintln!("Built without the audio feature, so there's no sound");
+    }
+    None
+}
+
+// write out the end of the recording, so the file can be played
+fn finish_recording(recording: &Option<Recording>, cpu: &CPU) {
+    if let Some(recording) = recording {
+        if let Err(e) = recording.lock().unwrap().finish(cpu.clock().elapsed()) {
+            eprintln!("Unable to save the audio recording: {}", e);
+        }
+    }
+}
+
+// make the CPU and apply the options to it
+fn load(prog: &[u8], profile: Profile, options: &Options) -> CPU {
+    let mut cpu = match potato::init_with(prog, profile) {
+        Ok(cpu) => cpu,
+        Err(e) => {
+            eprintln!("Unable to load program: {}", e);
+            exit(1);
+        }
+    };
+    if let Some(font) = &options.font {
+        cpu.load_font(font);
+    }
+    if let Some(rng) = options.rng {
+        cpu.set_random_source(rng);
+    }
+
+    cpu
+}
+
+fn start_recording(options: &Options) -> Option<Recording> {
+    let path = options.record_audio.as_ref()?;
+    let waveform = options.waveform.unwrap_or_default();
+    let rate = options.sample_rate.unwrap_or(DEFAULT_SAMPLE_RATE);
+    match WavSink::create(path, rate, waveform) {
+        Ok(wav) => Some(Arc::new(Mutex::new(wav))),
+        Err(e) => {
+            eprintln!("Unable to create {}: {}", path, e);
+            exit(1);
+        }
+    }
+}
+
+// save the display to `path`
+fn save_image(cpu: &CPU, format: ImageFormat, path: &Path) {
+    let result = File::create(path).and_then(|f| {
+        let mut out = BufWriter::new(f);
+        format.write(cpu, &mut out)?;
+        out.flush()
+    });
+    if let Err(e) = result {
+        eprintln!("Unable to save {}: {}", path.display(), e);
+        exit(1);
+    }
+}
+
+fn run_headless(prog: &[u8], profile: Profile, options: Options, headless: Headless) {
+    let mut cpu = load(prog, profile, &options);
+    let recording = start_recording(&options);
+    if let Some(recording) = &recording {
+        cpu.set_audio_sink(Box::new(recording.clone()));
+    }
+
+    // the format can come from the output file's extension
+    let format = headless
+        .format
+        .or_else(|| {
+            let path = Path::new(headless.output.as_ref()?);
+            path.extension()?.to_str()?.parse().ok()
+        })
+        .unwrap_or(ImageFormat::Ascii);
+    if let Some(dir) = &headless.dump_frames {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!("Unable to create {}: {}", dir, e);
+            exit(1);
+        }
+    }
+
+    let frames = headless.frames.unwrap_or(DEFAULT_HEADLESS_FRAMES);
+    let result = potato::run_headless(&mut cpu, frames, &headless.keys, |frame, cpu| {
+        if let Some(dir) = &headless.dump_frames {
+            let name = format!("{:06}.{}", frame, format.extension());
+            save_image(cpu, format, &Path::new(dir).join(name));
+        }
+    });
+    finish_recording(&recording, &cpu);
+    if let Err(e) = result {
+        eprintln!("Program stopped: {}\n{}", e, cpu);
         exit(1);
-    } else {
-        let path = &args[1];
-        if let Ok(prog) = std::fs::read(path) {
-            run(&prog);
+    }
+
+    match &headless.output {
+        Some(path) => save_image(&cpu, format, Path::new(path)),
+        None => {
+            let mut out = std::io::stdout().lock();
+            if let Err(e) = format.write(&cpu, &mut out) {
+                eprintln!("Unable to write the display: {}", e);
+                exit(1);
+            }
         }
     }
 }
 
-fn run(prog: &[u8]) {
+fn run(prog: &[u8], profile: Profile, options: Options) {
     env_logger::init();
-    let cpu = potato::init(prog);
-    let (window, events, mut px) = potato::display::init();
-    let mut last = Instant::now();
-    let mut timers = 0;
-    let mut elapsed = 0;
+    let mut cpu = load(prog, profile, &options);
+    let recording = start_recording(&options);
+    let mut sinks: Vec<Box<dyn AudioSink>> = vec![];
+    if let Some(recording) = &recording {
+        sinks.push(Box::new(recording.clone()));
+    }
+    // kept until the program exits, since the sound stops when it's dropped
+    let _audio = start_audio(&options, &mut sinks);
+    cpu.set_audio_sink(Box::new(sinks));
+    let speed = options.speed.unwrap_or(1.0);
+    let frame_time = Duration::from_nanos(1_000_000_000 / potato::FRAMES_PER_SECOND);
+    let (width, height) = cpu.resolution();
+    let (window, events, mut px) = potato::display::init(width, height);
+    let mut buffer_size = (width, height);
+    let fast_forward = Arc::new(AtomicBool::new(false));
 
     let window = Arc::new(window);
     let cpu = Arc::new(Mutex::new(cpu));
 
     let c1 = cpu.clone();
-    // let w1 = window.clone();
-    thread::spawn(move || loop {
-        let now = Instant::now();
-        let delta = now.duration_since(last);
-        last = now;
-        timers += delta.as_nanos();
-        elapsed += delta.as_nanos();
-        if timers >= (1_000_000_000 / 60) {
-            c1.lock().unwrap().timers();
-            timers = 0;
-        }
-
-        if elapsed >= (1_000_000_000 / 700) {
-            if c1.lock().unwrap().tick() {
-                // w1.lock().unwrap().request_redraw();
+    let ff = fast_forward.clone();
+    let rec = recording.clone();
+    // the CPU keeps its own time, all this does is tell it how much real time has passed
+    thread::spawn(move || {
+        let mut last = Instant::now();
+        let mut target = c1.lock().unwrap().clock().elapsed();
+        loop {
+            let now = Instant::now();
+            let factor = if ff.load(Ordering::Relaxed) {
+                speed * FAST_FORWARD
+            } else {
+                speed
+            };
+            target += (now - last).mul_f64(factor);
+            last = now;
+
+            {
+                let mut c = c1.lock().unwrap();
+                // don't try to catch up on time lost to the machine being busy
+                target = target
+                    .max(c.clock().elapsed())
+                    .min(c.clock().elapsed() + MAX_CATCH_UP);
+                let cycles = c
+                    .clock()
+                    .cycles_at(target)
+                    .saturating_sub(c.clock().cycles());
+                if let Err(e) = c.run_cycles(cycles) {
+                    eprintln!("Program stopped: {}\n{}", e, c);
+                    finish_recording(&rec, &c);
+                    exit(1);
+                }
             }
-            elapsed = 0;
-        }
 
-        //Don't spin as fast as the CPU will let us
-        thread::sleep(Duration::from_nanos(500));
+            thread::sleep(frame_time / 4);
+        }
     });
 
     events.run(move |event, _, flow| {
@@ -80,28 +540,32 @@ fn run(prog: &[u8]) {
                     is_synthetic: _,
                 } => {
                     // debug!("input: {}", input.scancode);
+                    let pressed = input.state == ElementState::Pressed;
                     if let Some(k) = DEFAULT_KEYPAD.get(&input.scancode) {
-                        cpu.lock().unwrap().keypad[*k] = match input.state {
-                            ElementState::Pressed => true,
-                            _ => false,
-                        }
-                    } else {
-                        match input.virtual_keycode {
-                            Some(VirtualKeyCode::Escape) => {
-                                *flow = ControlFlow::Exit;
-                            }
-                            _ => {}
-                        }
+                        cpu.lock().unwrap().set_key(0, *k, pressed);
+                    } else if let Some(k) = SECOND_KEYPAD.get(&input.scancode) {
+                        cpu.lock().unwrap().set_key(1, *k, pressed);
+                    } else if input.virtual_keycode == Some(VirtualKeyCode::Tab) {
+                        fast_forward.store(pressed, Ordering::Relaxed);
+                    } else if input.virtual_keycode == Some(VirtualKeyCode::Escape) {
+                        *flow = ControlFlow::Exit;
                     }
                 }
                 _ => {}
             },
             Event::RedrawRequested(_) => {
-                cpu.lock().unwrap().draw(px.get_frame_mut());
+                let cpu = cpu.lock().unwrap();
+                // SCHIP programs can switch resolution at any time
+                if cpu.resolution() != buffer_size {
+                    buffer_size = cpu.resolution();
+                    px.resize_buffer(buffer_size.0 as u32, buffer_size.1 as u32);
+                }
+                cpu.draw(px.get_frame_mut());
                 if px.render().is_err() {
                     *flow = ControlFlow::Exit;
                 }
             }
+            Event::LoopDestroyed => finish_recording(&recording, &cpu.lock().unwrap()),
             _ => {}
         }
     });
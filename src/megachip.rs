@@ -0,0 +1,170 @@
+/// The size of the MegaChip display
+pub const MEGA_WIDTH: usize = 256;
+pub const MEGA_HEIGHT: usize = 192;
+
+/// How a MegaChip sprite's colours are combined with what's already on the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlendMode {
+    /// the sprite replaces the screen
+    Normal,
+    /// the sprite is drawn at 25% opacity
+    Percent25,
+    /// the sprite is drawn at 50% opacity
+    Percent50,
+    /// the sprite is drawn at 75% opacity
+    Percent75,
+    /// each colour channel of the sprite is added to the screen
+    Additive,
+    /// each colour channel of the sprite is multiplied with the screen
+    Multiply,
+}
+
+impl BlendMode {
+    /// The blend mode selected by 080N, if N is a valid mode
+    pub fn from_n(n: u8) -> Option<Self> {
+        Some(match n {
+            0 => BlendMode::Normal,
+            1 => BlendMode::Percent25,
+            2 => BlendMode::Percent50,
+            3 => BlendMode::Percent75,
+            4 => BlendMode::Additive,
+            5 => BlendMode::Multiply,
+            _ => return None,
+        })
+    }
+
+    // combine an ARGB sprite colour with an ARGB screen colour
+    fn blend(self, src: u32, dst: u32) -> u32 {
+        let channel = |c: u32, shift: u32| (c >> shift) & 0xFF;
+        let mix = |f: fn(u32, u32) -> u32| {
+            [16, 8, 0].into_iter().fold(0xFF00_0000, |out, shift| {
+                out | (f(channel(src, shift), channel(dst, shift)).min(0xFF) << shift)
+            })
+        };
+
+        match self {
+            BlendMode::Normal => src,
+            BlendMode::Percent25 => mix(|s, d| (s + d * 3) / 4),
+            BlendMode::Percent50 => mix(|s, d| (s + d) / 2),
+            BlendMode::Percent75 => mix(|s, d| (s * 3 + d) / 4),
+            BlendMode::Additive => mix(|s, d| s + d),
+            BlendMode::Multiply => mix(|s, d| s * d / 0xFF),
+        }
+    }
+}
+
+/// A digitised sound started by 060N
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+    /// samples per second
+    pub rate: u16,
+    /// unsigned 8-bit samples
+    pub data: Vec<u8>,
+    /// whether the sound starts again once it reaches the end
+    pub looping: bool,
+}
+
+/// The state used while MegaChip mode is on.
+///
+/// The screen is double buffered: sprites are drawn to the back buffer, and 00E0 shows it and
+/// starts a new one.
+#[derive(Debug, Clone)]
+pub(crate) struct MegaChip {
+    // ARGB colours loaded with 02NN, index 0 is always transparent
+    palette: [u32; 256],
+    pub sprite_width: usize,
+    pub sprite_height: usize,
+    pub alpha: u8,
+    pub blend: BlendMode,
+    // drawing over a pixel of this palette index sets VF
+    pub collision: u8,
+    back: Vec<u32>,
+    front: Vec<u32>,
+    // the palette index of every pixel in the back buffer, for collision detection
+    indices: Vec<u8>,
+    pub sample: Option<Sample>,
+}
+
+impl MegaChip {
+    pub fn new() -> Self {
+        Self {
+            palette: [0xFF00_0000; 256],
+            sprite_width: 0,
+            sprite_height: 0,
+            alpha: 0xFF,
+            blend: BlendMode::Normal,
+            collision: 0,
+            back: vec![0xFF00_0000; MEGA_WIDTH * MEGA_HEIGHT],
+            front: vec![0xFF00_0000; MEGA_WIDTH * MEGA_HEIGHT],
+            indices: vec![0; MEGA_WIDTH * MEGA_HEIGHT],
+            sample: None,
+        }
+    }
+
+    /// Load ARGB colours, four bytes each, into the palette starting at index 1
+    pub fn load_palette(&mut self, colours: &[u8]) {
+        for (i, argb) in colours.chunks_exact(4).enumerate().take(255) {
+            self.palette[i + 1] = u32::from_be_bytes([argb[0], argb[1], argb[2], argb[3]]);
+        }
+    }
+
+    /// Blend a palette colour onto the back buffer, returning true if it collided
+    pub fn plot(&mut self, x: usize, y: usize, index: u8) -> bool {
+        let i = y * MEGA_WIDTH + x;
+        let collided = self.collision != 0 && self.indices[i] == self.collision;
+        self.back[i] = self.blend.blend(self.palette[index as usize], self.back[i]);
+        self.indices[i] = index;
+        collided
+    }
+
+    /// Toggle a pixel of a one bit sprite, such as a font character, returning true if it was turned off
+    pub fn toggle(&mut self, x: usize, y: usize) -> bool {
+        let i = y * MEGA_WIDTH + x;
+        let on = self.indices[i] == 0xFF;
+        (self.back[i], self.indices[i]) = if on {
+            (0xFF00_0000, 0)
+        } else {
+            (0xFFFF_FFFF, 0xFF)
+        };
+        on
+    }
+
+    /// Show the back buffer, and clear it for the next frame
+    pub fn present(&mut self) {
+        self.front.copy_from_slice(&self.back);
+        self.back.fill(0xFF00_0000);
+        self.indices.fill(0);
+    }
+
+    /// Move the back buffer by (dx, dy) pixels, filling the gap with black
+    pub fn scroll(&mut self, dx: isize, dy: isize) {
+        let old_back = self.back.clone();
+        let old_indices = self.indices.clone();
+        for y in 0..MEGA_HEIGHT {
+            for x in 0..MEGA_WIDTH {
+                let src_x = x as isize - dx;
+                let src_y = y as isize - dy;
+                let i = y * MEGA_WIDTH + x;
+                if (0..MEGA_WIDTH as isize).contains(&src_x)
+                    && (0..MEGA_HEIGHT as isize).contains(&src_y)
+                {
+                    let src = src_y as usize * MEGA_WIDTH + src_x as usize;
+                    self.back[i] = old_back[src];
+                    self.indices[i] = old_indices[src];
+                } else {
+                    self.back[i] = 0xFF00_0000;
+                    self.indices[i] = 0;
+                }
+            }
+        }
+    }
+
+    /// Draw the front buffer into an RGBA frame buffer, faded by the screen alpha
+    pub fn render(&self, frame: &mut [u8]) {
+        for (pixel, argb) in frame.chunks_exact_mut(4).zip(&self.front) {
+            let [_, r, g, b] = argb.to_be_bytes();
+            let fade = |c: u8| (c as u16 * self.alpha as u16 / 0xFF) as u8;
+            pixel.copy_from_slice(&[fade(r), fade(g), fade(b), 0xFF]);
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::ops::Range;
+
+use crate::font::{BIG_FONT_START, FONT_START};
+
+/// What a part of memory is used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    /// reserved for the interpreter, programs shouldn't touch it
+    Interpreter,
+    /// the small font that FX29 points into
+    Font,
+    /// the big font that FX30 points into
+    BigFont,
+    /// where the program is loaded, and the rest of memory it's free to use
+    Program,
+}
+
+/// How the address space is laid out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemoryMap {
+    /// the size of the address space in bytes
+    pub size: usize,
+    pub font: Range<usize>,
+    pub big_font: Range<usize>,
+    /// from the address programs are loaded at to the end of memory
+    pub program: Range<usize>,
+}
+
+impl MemoryMap {
+    pub fn new(size: usize, load_address: usize) -> Self {
+        Self {
+            size,
+            font: FONT_START..FONT_START + 80,
+            big_font: BIG_FONT_START..BIG_FONT_START + 160,
+            program: load_address.min(size)..size,
+        }
+    }
+
+    /// What the byte at `addr` is used for, or `None` if it's past the end of memory
+    pub fn region(&self, addr: usize) -> Option<Region> {
+        Some(match addr {
+            _ if addr >= self.size => return None,
+            _ if self.program.contains(&addr) => Region::Program,
+            _ if self.font.contains(&addr) => Region::Font,
+            _ if self.big_font.contains(&addr) => Region::BigFont,
+            _ => Region::Interpreter,
+        })
+    }
+
+    /// The parts of memory in address order, with the range each one covers
+    pub fn regions(&self) -> Vec<(Region, Range<usize>)> {
+        let mut bounds = vec![
+            0,
+            self.font.start,
+            self.font.end,
+            self.big_font.start,
+            self.big_font.end,
+            self.program.start,
+            self.size,
+        ];
+        bounds.retain(|b| *b <= self.size);
+        bounds.sort_unstable();
+        bounds.dedup();
+
+        let mut regions: Vec<(Region, Range<usize>)> = vec![];
+        for pair in bounds.windows(2) {
+            let Some(region) = self.region(pair[0]) else {
+                continue;
+            };
+            match regions.last_mut() {
+                Some((last, range)) if *last == region => range.end = pair[1],
+                _ => regions.push((region, pair[0]..pair[1])),
+            }
+        }
+
+        regions
+    }
+}
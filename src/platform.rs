@@ -0,0 +1,277 @@
+use std::{fmt::Display, str::FromStr};
+
+use crate::{
+    cpu::{HEIGHT, TWO_PAGE_HEIGHT, WIDTH},
+    font::FontSet,
+    instruction::InstructionSet,
+    memory::MemoryMap,
+    quirks::{LoadStore, Quirks},
+    timing::Timing,
+};
+
+/// The interpreters that CHIP-8 programs were commonly written for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    /// The original interpreter on the RCA COSMAC VIP
+    CosmacVip,
+    /// The two-page hi-res interpreter on the COSMAC VIP, with a 64x64 display
+    CosmacVipHires,
+    /// CHIP-48 on the HP 48 calculators
+    Chip48,
+    /// SUPER-CHIP 1.0 on the HP 48 calculators
+    Schip10,
+    /// SUPER-CHIP 1.1 on the HP 48 calculators
+    Schip11,
+    /// SUPER-CHIP as implemented by most modern interpreters, such as Octo
+    SchipModern,
+    /// XO-CHIP, as defined by Octo
+    XoChip,
+    /// MegaChip8, with its 256x192 colour display
+    MegaChip,
+    /// CHIP-8X on the COSMAC VIP, with the colour board and a second keypad
+    Chip8X,
+    /// The ETI-660, which loads programs at 0x600
+    Eti660,
+}
+
+/// Everything that needs to be picked to run a program the way its platform would
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    pub instruction_set: InstructionSet,
+    pub quirks: Quirks,
+    /// how many instructions are executed for every 60Hz timer tick, with the simple timing model
+    pub instructions_per_frame: u32,
+    pub timing: Timing,
+    /// the display resolution the program starts with
+    pub width: usize,
+    pub height: usize,
+    /// the size of the address space in bytes, which must be at least `MIN_MEMORY_SIZE`
+    pub memory_size: usize,
+    /// where programs are loaded, and start running from
+    pub load_address: usize,
+    /// how many return addresses the call stack can hold, or `None` for no limit
+    pub stack_depth: Option<usize>,
+    /// the font loaded into memory for FX29 and FX30
+    pub font: FontSet,
+    /// whether programs are for the two-page hi-res interpreter, which skips the `1260` jump
+    /// they start with and clears the screen with 0230
+    pub two_page: bool,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            instruction_set: InstructionSet::Chip8,
+            quirks: Quirks::default(),
+            instructions_per_frame: 12,
+            timing: Timing::Simple,
+            width: WIDTH,
+            height: HEIGHT,
+            memory_size: 4096,
+            load_address: 0x200,
+            stack_depth: Some(16),
+            font: FontSet::Schip,
+            two_page: false,
+        }
+    }
+}
+
+impl Profile {
+    /// How memory is laid out for this profile
+    pub fn memory_map(&self) -> MemoryMap {
+        MemoryMap::new(self.memory_size, self.load_address)
+    }
+}
+
+impl Platform {
+    pub const ALL: [Platform; 10] = [
+        Platform::CosmacVip,
+        Platform::CosmacVipHires,
+        Platform::Chip48,
+        Platform::Schip10,
+        Platform::Schip11,
+        Platform::SchipModern,
+        Platform::XoChip,
+        Platform::MegaChip,
+        Platform::Chip8X,
+        Platform::Eti660,
+    ];
+
+    /// The short name used to select this platform on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::CosmacVip => "vip",
+            Platform::CosmacVipHires => "vip-hires",
+            Platform::Chip48 => "chip48",
+            Platform::Schip10 => "schip1.0",
+            Platform::Schip11 => "schip1.1",
+            Platform::SchipModern => "schip",
+            Platform::XoChip => "xochip",
+            Platform::MegaChip => "megachip",
+            Platform::Chip8X => "chip8x",
+            Platform::Eti660 => "eti660",
+        }
+    }
+
+    pub fn profile(self) -> Profile {
+        // the HP 48 interpreters all share the same quirks, apart from how they treat the index in FX55/FX65
+        let hp48 = Quirks {
+            shift_uses_vy: false,
+            load_store: LoadStore::IncrementX,
+            jump_with_vx: true,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+            key_release: false,
+            index_overflow: false,
+            lores_on_hires: false,
+            vf_row_count: false,
+        };
+
+        let vip = Quirks {
+            shift_uses_vy: true,
+            load_store: LoadStore::IncrementXPlusOne,
+            jump_with_vx: false,
+            vf_reset: true,
+            clip_sprites: true,
+            display_wait: true,
+            key_release: true,
+            index_overflow: false,
+            lores_on_hires: false,
+            vf_row_count: false,
+        };
+
+        match self {
+            Platform::CosmacVip => Profile {
+                quirks: vip,
+                instructions_per_frame: 15,
+                stack_depth: Some(12),
+                font: FontSet::CosmacVip,
+                ..Profile::default()
+            },
+            Platform::CosmacVipHires => Profile {
+                height: TWO_PAGE_HEIGHT,
+                two_page: true,
+                ..Platform::CosmacVip.profile()
+            },
+            // CHIP-8X is an extension of the VIP interpreter, so it has the same quirks
+            Platform::Chip8X => Profile {
+                instruction_set: InstructionSet::Chip8X,
+                quirks: vip,
+                instructions_per_frame: 15,
+                // the CHIP-8X interpreter is bigger, so programs start a page later
+                load_address: 0x300,
+                stack_depth: Some(12),
+                font: FontSet::CosmacVip,
+                ..Profile::default()
+            },
+            Platform::Eti660 => Profile {
+                // the ETI 660 has a 64x48 display
+                height: 48,
+                quirks: vip,
+                instructions_per_frame: 15,
+                load_address: 0x600,
+                stack_depth: Some(12),
+                font: FontSet::Eti660,
+                ..Profile::default()
+            },
+            Platform::Chip48 => Profile {
+                quirks: hp48,
+                instructions_per_frame: 30,
+                ..Profile::default()
+            },
+            Platform::Schip10 => Profile {
+                instruction_set: InstructionSet::Schip10,
+                quirks: Quirks {
+                    lores_on_hires: true,
+                    ..hp48
+                },
+                instructions_per_frame: 30,
+                ..Profile::default()
+            },
+            Platform::Schip11 => Profile {
+                instruction_set: InstructionSet::Schip,
+                quirks: Quirks {
+                    load_store: LoadStore::Unchanged,
+                    lores_on_hires: true,
+                    vf_row_count: true,
+                    ..hp48
+                },
+                instructions_per_frame: 30,
+                ..Profile::default()
+            },
+            // modern interpreters keep the two resolutions apart, and don't count rows for VF
+            Platform::SchipModern => Profile {
+                instruction_set: InstructionSet::Schip,
+                quirks: Quirks {
+                    load_store: LoadStore::Unchanged,
+                    ..hp48
+                },
+                instructions_per_frame: 30,
+                ..Profile::default()
+            },
+            Platform::XoChip => Profile {
+                instruction_set: InstructionSet::XoChip,
+                quirks: Quirks {
+                    shift_uses_vy: true,
+                    load_store: LoadStore::IncrementXPlusOne,
+                    jump_with_vx: false,
+                    vf_reset: false,
+                    clip_sprites: false,
+                    display_wait: false,
+                    key_release: true,
+                    index_overflow: false,
+                    lores_on_hires: false,
+                    vf_row_count: false,
+                },
+                instructions_per_frame: 1000,
+                memory_size: 0x10000,
+                ..Profile::default()
+            },
+            Platform::MegaChip => Profile {
+                instruction_set: InstructionSet::MegaChip,
+                quirks: Quirks {
+                    load_store: LoadStore::Unchanged,
+                    ..hp48
+                },
+                instructions_per_frame: 1000,
+                memory_size: 0x100_0000,
+                ..Profile::default()
+            },
+        }
+    }
+}
+
+impl Display for Platform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Returned when a platform name isn't recognised
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownPlatform(pub String);
+
+impl Display for UnknownPlatform {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown platform '{}', expected one of:", self.0)?;
+        for p in Platform::ALL {
+            write!(f, " {}", p)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for UnknownPlatform {}
+
+impl FromStr for Platform {
+    type Err = UnknownPlatform;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Platform::ALL
+            .into_iter()
+            .find(|p| p.name().eq_ignore_ascii_case(s))
+            .ok_or_else(|| UnknownPlatform(s.to_string()))
+    }
+}
@@ -0,0 +1,57 @@
+/// How FX55 and FX65 leave the index register after storing or loading registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadStore {
+    /// COSMAC VIP: the index ends up at I + X + 1
+    IncrementXPlusOne,
+    /// CHIP-48 and SCHIP 1.0: the index ends up at I + X
+    IncrementX,
+    /// SCHIP 1.1 and modern interpreters: the index is left alone
+    Unchanged,
+}
+
+/// The behaviours that differ between CHIP-8 interpreters for the same opcode.
+///
+/// The default matches the behaviour of most modern interpreters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6/8XYE copy VY into VX before shifting, rather than shifting VX in place
+    pub shift_uses_vy: bool,
+    /// what FX55/FX65 do to the index register
+    pub load_store: LoadStore,
+    /// BNNN is read as BXNN, jumping to XNN + VX instead of NNN + V0
+    pub jump_with_vx: bool,
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0
+    pub vf_reset: bool,
+    /// sprites are clipped at the edges of the screen instead of wrapping around
+    pub clip_sprites: bool,
+    /// DXYN waits for the vertical blank, so nothing else runs for the rest of the frame
+    pub display_wait: bool,
+    /// FX0A waits for the key to be released before storing it, instead of storing it as soon as it's pressed
+    pub key_release: bool,
+    /// FX1E sets VF when the index goes past 0x0FFF, and clears it otherwise
+    pub index_overflow: bool,
+    /// low resolution is drawn with 2x2 pixels on the 128x64 high resolution display, as SCHIP
+    /// did on the HP 48. Switching resolution doesn't clear the screen, and scrolling moves by
+    /// high resolution pixels, which is half a pixel in low resolution
+    pub lores_on_hires: bool,
+    /// in high resolution, DXYN sets VF to the number of sprite rows that collided or were
+    /// clipped off the bottom of the screen, instead of 1
+    pub vf_row_count: bool,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Self {
+            shift_uses_vy: false,
+            load_store: LoadStore::Unchanged,
+            jump_with_vx: false,
+            vf_reset: false,
+            clip_sprites: true,
+            display_wait: false,
+            key_release: true,
+            index_overflow: false,
+            lores_on_hires: false,
+            vf_row_count: false,
+        }
+    }
+}
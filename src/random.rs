@@ -0,0 +1,94 @@
+// the second page of the COSMAC VIP's CHIP-8 interpreter, from 0x100 to 0x1FF, which its
+// generator reads from
+const VIP_INTERPRETER_PAGE: [u8; 256] = [
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x45, 0xA3, 0x98, 0x56, 0xD4, 0xF8, 0x81, 0xBC, 0xF8, 0x95, 0xAC,
+    0x22, 0xDC, 0x12, 0x56, 0xD4, 0x06, 0xB8, 0xD4, 0x06, 0xA8, 0xD4, 0x64, 0x0A, 0x01, 0xE6, 0x8A,
+    0xF4, 0xAA, 0x3B, 0x28, 0x9A, 0xFC, 0x01, 0xBA, 0xD4, 0xF8, 0x81, 0xBA, 0x06, 0xFA, 0x0F, 0xAA,
+    0x0A, 0xAA, 0xD4, 0xE6, 0x06, 0xBF, 0x93, 0xBE, 0xF8, 0x1B, 0xAE, 0x2A, 0x1A, 0xF8, 0x00, 0x5A,
+    0x0E, 0xF5, 0x3B, 0x4B, 0x56, 0x0A, 0xFC, 0x01, 0x5A, 0x30, 0x40, 0x4E, 0xF6, 0x3B, 0x3C, 0x9F,
+    0x56, 0x2A, 0x2A, 0xD4, 0x00, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x07, 0x5A, 0x87, 0xF3, 0x17,
+    0x1A, 0x3A, 0x5B, 0x12, 0xD4, 0x22, 0x86, 0x52, 0xF8, 0xF0, 0xA7, 0x0A, 0x57, 0x87, 0xF3, 0x17,
+    0x1A, 0x3A, 0x6B, 0x12, 0xD4, 0x15, 0x85, 0x22, 0x73, 0x95, 0x52, 0x25, 0x45, 0xA5, 0x86, 0xFA,
+    0x0F, 0xB5, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x82, 0x15, 0x15, 0xD4, 0x45, 0xE6, 0xF3, 0x3A, 0x88,
+    0xD4, 0x45, 0x07, 0x30, 0x8C, 0x45, 0x07, 0x30, 0x84, 0xE6, 0x62, 0x26, 0x45, 0xA3, 0x36, 0x88,
+    0xD4, 0x3E, 0x88, 0xD4, 0xF8, 0xF0, 0xA7, 0xE7, 0x45, 0xF4, 0xA5, 0x86, 0xFA, 0x0F, 0x3B, 0xB2,
+    0xFC, 0x01, 0xB5, 0xD4, 0x45, 0x56, 0xD4, 0x45, 0xE6, 0xF4, 0x56, 0xD4, 0x45, 0xFA, 0x0F, 0x3A,
+    0xC4, 0x07, 0x56, 0xD4, 0xAF, 0x22, 0xF8, 0xD3, 0x73, 0x8F, 0xF9, 0xF0, 0x52, 0xE6, 0x07, 0xD2,
+    0x56, 0xF8, 0xFF, 0xA6, 0xF8, 0x00, 0x7E, 0x56, 0xD4, 0x19, 0x89, 0xAE, 0x93, 0xBE, 0x99, 0xEE,
+    0xF4, 0x56, 0x76, 0xE6, 0xF4, 0xB9, 0x56, 0x45, 0xF2, 0x56, 0xD4, 0x45, 0xAA, 0x86, 0xFA, 0x0F,
+    0xBA, 0xD4, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xE0,
+];
+
+/// Where CXNN gets its random numbers from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RandomSource {
+    /// A SplitMix64 generator, which gives the same numbers every time it's started from the same seed
+    Seeded { state: u64 },
+    /// The COSMAC VIP interpreter's generator. A counter, which also counts frames, picks a byte
+    /// of the interpreter's own code and mixes it with the last number.
+    CosmacVip { counter: u16 },
+}
+
+impl RandomSource {
+    pub fn seeded(seed: u64) -> Self {
+        RandomSource::Seeded { state: seed }
+    }
+
+    pub fn cosmac_vip() -> Self {
+        RandomSource::CosmacVip { counter: 0 }
+    }
+
+    /// The next random byte
+    pub fn next_byte(&mut self) -> u8 {
+        match self {
+            RandomSource::Seeded { state } => {
+                *state = state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+                let mut z = *state;
+                z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+                z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+                ((z ^ (z >> 31)) >> 56) as u8
+            }
+            RandomSource::CosmacVip { counter } => {
+                // the interpreter adds the byte to the last number, then adds that to itself
+                // shifted right with the carry shifted in at the top
+                let [hi, lo] = counter.to_be_bytes();
+                let lo = lo.wrapping_add(1);
+                let (sum, carry) = VIP_INTERPRETER_PAGE[lo as usize].overflowing_add(hi);
+                let hi = sum.wrapping_add(sum >> 1 | (carry as u8) << 7);
+                *counter = u16::from_be_bytes([hi, lo]);
+                hi
+            }
+        }
+    }
+
+    /// Called on every 60Hz timer tick, which the VIP's generator counts
+    pub fn frame(&mut self) {
+        if let RandomSource::CosmacVip { counter } = self {
+            *counter = counter.wrapping_add(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vip_generator_spreads_its_numbers() {
+        let mut rng = RandomSource::cosmac_vip();
+        let mut seen = [false; 256];
+        for _ in 0..600 {
+            seen[rng.next_byte() as usize] = true;
+            rng.frame();
+        }
+        assert!(seen.iter().filter(|s| **s).count() > 64);
+    }
+
+    #[test]
+    fn vip_generator_steps() {
+        let mut rng = RandomSource::CosmacVip { counter: 0x10D8 };
+        // 0x10 + 0x19 = 0x29, and 0x29 + 0x14 = 0x3D
+        assert_eq!(rng.next_byte(), 0x3D);
+        assert_eq!(rng, RandomSource::CosmacVip { counter: 0x3DD9 });
+    }
+}
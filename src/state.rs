@@ -0,0 +1,33 @@
+use crate::{clock::Clock, framebuffer::Framebuffer, random::RandomSource};
+
+/// A copy of everything a CHIP-8, SCHIP, or XO-CHIP program can see or change, taken with
+/// `CPU::state` and put back with `CPU::restore`.
+///
+/// The MegaChip colour screen and CHIP-8X colours aren't included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CpuState {
+    pub pc: usize,
+    pub index: u32,
+    pub registers: [u8; 16],
+    /// return addresses, from the outermost call to the innermost
+    pub stack: Vec<usize>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub memory: Vec<u8>,
+    pub display: Framebuffer,
+    /// whether the SCHIP high resolution mode is on
+    pub hires: bool,
+    /// the XO-CHIP bitplanes selected for drawing
+    pub planes: u8,
+    /// the XO-CHIP audio pattern buffer and pitch
+    pub pattern: [u8; 16],
+    pub pitch: u8,
+    /// the SCHIP RPL user flags
+    pub flags: [u8; 16],
+    pub keypad: [[bool; 16]; 2],
+    pub halted: bool,
+    /// the random number generator, so a restored state carries on with the same numbers
+    pub rng: RandomSource,
+    /// emulated time, including how far through the current frame it is
+    pub clock: Clock,
+}
@@ -0,0 +1,83 @@
+use crate::instruction::Instruction;
+
+/// Machine cycles in a COSMAC VIP frame: the 1.76 MHz clock, 8 clocks to a cycle, 60 frames a second
+pub const VIP_CYCLES_PER_FRAME: u64 = 3668;
+// cycles of every frame lost to the display DMA and the interrupt routine that runs the timers
+const VIP_FRAME_OVERHEAD: u64 = 1024 + 48;
+
+/// How long instructions take to run
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Timing {
+    /// every instruction takes one cycle, with a fixed number of instructions in each frame
+    #[default]
+    Simple,
+    /// each instruction takes about as many machine cycles as it did in the COSMAC VIP's
+    /// interpreter, with part of every frame taken up by the display and the timer interrupt
+    CosmacVip,
+}
+
+impl Timing {
+    /// How many cycles the clock counts in a frame, given the profile's instructions per frame
+    pub fn cycles_per_frame(self, instructions_per_frame: u32) -> u64 {
+        match self {
+            Timing::Simple => instructions_per_frame.into(),
+            Timing::CosmacVip => VIP_CYCLES_PER_FRAME,
+        }
+    }
+
+    /// Cycles taken away from the program at the start of every frame
+    pub fn frame_overhead(self) -> u64 {
+        match self {
+            Timing::Simple => 0,
+            Timing::CosmacVip => VIP_FRAME_OVERHEAD,
+        }
+    }
+
+    /// How many cycles an instruction takes, given the registers before it runs
+    pub fn cycles(self, instr: Instruction, registers: &[u8; 16]) -> u64 {
+        if self == Timing::Simple {
+            return 1;
+        }
+
+        use Instruction::*;
+        let v = |x: u8| registers[x as usize];
+        match instr {
+            Clear => 24,
+            Return | Sys(_) | Jump(_) => 23,
+            Call(_) => 26,
+            SkipEqByte { .. } | SkipNeByte { .. } => 14,
+            SkipEqReg { .. } | SkipNeReg { .. } | SkipKey { .. } | SkipNotKey { .. } => 18,
+            LoadByte { .. } => 6,
+            AddByte { .. } => 10,
+            LoadReg { .. }
+            | Or { .. }
+            | And { .. }
+            | Xor { .. }
+            | AddReg { .. }
+            | Sub { .. }
+            | ShiftRight { .. }
+            | SubN { .. }
+            | ShiftLeft { .. } => 44,
+            LoadIndex(_) => 12,
+            JumpOffset(_) => 22,
+            Random { .. } => 36,
+            // sprites are drawn a byte at a time, so a row that isn't lined up with a byte of
+            // the display takes twice the work
+            Draw { x, n, .. } => {
+                let row = if v(x) % 8 == 0 { 22 } else { 34 };
+                34 + n as u64 * row
+            }
+            LoadDelay { .. } | WaitKey { .. } | SetDelay { .. } | SetSound { .. } => 10,
+            AddIndex { .. } => 16,
+            LoadFont { .. } => 20,
+            // each digit is found by repeated subtraction
+            StoreBcd { x } => {
+                let digits = v(x) / 100 + v(x) / 10 % 10 + v(x) % 10;
+                84 + 12 * digits as u64
+            }
+            StoreRegs { x } | LoadRegs { x } => 14 + 14 * (x as u64 + 1),
+            // none of the other instructions exist on the VIP
+            _ => 10,
+        }
+    }
+}
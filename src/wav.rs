@@ -0,0 +1,186 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    time::Duration,
+};
+
+use crate::{
+    audio::{AudioSink, Synth, Waveform},
+    megachip::Sample,
+};
+
+// the size of the RIFF and format headers, before the samples start
+const HEADER_SIZE: u32 = 44;
+
+/// An audio sink that records the sound to a 16-bit mono WAV file.
+///
+/// Samples are written in emulated time, so the recording lines up exactly with the timers
+/// however fast the program is really run, and the same program always makes the same file.
+pub struct WavSink<W: Write + Seek + Send> {
+    out: W,
+    synth: Synth,
+    // how many samples have been written so far
+    samples: u64,
+    // the first error writing failed with, since sinks can't return errors to the CPU
+    error: Option<io::Error>,
+}
+
+impl WavSink<BufWriter<File>> {
+    /// Record to a new file at `path`, replacing it if it already exists
+    pub fn create(path: impl AsRef<Path>, rate: u32, waveform: Waveform) -> io::Result<Self> {
+        Self::new(BufWriter::new(File::create(path)?), rate, waveform)
+    }
+}
+
+impl<W: Write + Seek + Send> WavSink<W> {
+    pub fn new(mut out: W, rate: u32, waveform: Waveform) -> io::Result<Self> {
+        let synth = Synth::new(rate, waveform);
+        // the sizes are filled in by `finish`
+        write_header(&mut out, synth.rate(), 0)?;
+        Ok(Self {
+            out,
+            synth,
+            samples: 0,
+            error: None,
+        })
+    }
+
+    /// The synth the samples come from, to change the volume or the pitch of the beep
+    pub fn synth_mut(&mut self) -> &mut Synth {
+        &mut self.synth
+    }
+
+    /// How many samples have been written
+    pub fn samples(&self) -> u64 {
+        self.samples
+    }
+
+    /// Get back what the recording was written to
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    /// Write the sound up to `at` emulated time
+    pub fn advance(&mut self, at: Duration) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+
+        // rounded to the nearest sample, since the clock's times are rounded down to the nanosecond
+        let end =
+            ((at.as_nanos() * self.synth.rate() as u128 + 500_000_000) / 1_000_000_000) as u64;
+        while self.samples < end {
+            let sample = (self.synth.next_sample() * i16::MAX as f32) as i16;
+            self.out.write_all(&sample.to_le_bytes())?;
+            self.samples += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Write the sound up to `at`, which should be when the program stopped, and fill in the
+    /// header so the file can be played
+    pub fn finish(&mut self, at: Duration) -> io::Result<()> {
+        self.advance(at)?;
+        let data_size = u32::try_from(self.samples * 2)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "recording is over 4GB"))?;
+        self.out.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.out, self.synth.rate(), data_size)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+
+    // write the samples from before a change, keeping hold of any error for later
+    fn catch_up(&mut self, at: Duration) {
+        if self.error.is_none() {
+            self.error = self.advance(at).err();
+        }
+    }
+}
+
+impl<W: Write + Seek + Send> AudioSink for WavSink<W> {
+    fn beeper(&mut self, on: bool, at: Duration) {
+        self.catch_up(at);
+        self.synth.set_beeper(on);
+    }
+
+    fn pattern(&mut self, pattern: &[u8; 16], pitch: u8, at: Duration) {
+        self.catch_up(at);
+        self.synth.set_pattern(pattern, pitch);
+    }
+
+    fn sample(&mut self, sample: Option<&Sample>, at: Duration) {
+        self.catch_up(at);
+        self.synth.set_sample(sample);
+    }
+}
+
+fn write_header(out: &mut impl Write, rate: u32, data_size: u32) -> io::Result<()> {
+    let byte_rate = rate
+        .checked_mul(2)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "sample rate is too high"))?;
+    let riff_size = (HEADER_SIZE - 8)
+        .checked_add(data_size)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "recording is over 4GB"))?;
+
+    out.write_all(b"RIFF")?;
+    out.write_all(&riff_size.to_le_bytes())?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_all(&16u32.to_le_bytes())?;
+    // PCM, one channel
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&1u16.to_le_bytes())?;
+    out.write_all(&rate.to_le_bytes())?;
+    // bytes per second, bytes per sample, and bits per sample
+    out.write_all(&byte_rate.to_le_bytes())?;
+    out.write_all(&2u16.to_le_bytes())?;
+    out.write_all(&16u16.to_le_bytes())?;
+
+    out.write_all(b"data")?;
+    out.write_all(&data_size.to_le_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn records_the_beeper() {
+        let mut wav = WavSink::new(Cursor::new(Vec::new()), 8000, Waveform::Square).unwrap();
+        wav.beeper(true, Duration::from_millis(10));
+        wav.beeper(false, Duration::from_millis(20));
+        wav.finish(Duration::from_millis(30)).unwrap();
+        assert_eq!(wav.samples(), 240);
+
+        let bytes = wav.into_inner().into_inner();
+        assert_eq!(bytes.len(), 44 + 480);
+        // the RIFF size, the sample rate, bytes per second, and the size of the samples
+        assert_eq!(u32_at(&bytes, 4), 36 + 480);
+        assert_eq!(u32_at(&bytes, 24), 8000);
+        assert_eq!(u32_at(&bytes, 28), 16000);
+        assert_eq!(u32_at(&bytes, 40), 480);
+
+        // the beep is only heard between 10ms and 20ms
+        let samples: Vec<i16> = bytes[44..]
+            .chunks_exact(2)
+            .map(|s| i16::from_le_bytes([s[0], s[1]]))
+            .collect();
+        assert!(samples[..80].iter().all(|s| *s == 0));
+        assert!(samples[80..160].iter().all(|s| *s != 0));
+        assert!(samples[160..].iter().all(|s| *s == 0));
+    }
+
+    #[test]
+    fn sample_rate_too_high() {
+        let wav = WavSink::new(Cursor::new(Vec::new()), u32::MAX, Waveform::Square);
+        assert_eq!(wav.err().unwrap().kind(), io::ErrorKind::InvalidInput);
+    }
+}